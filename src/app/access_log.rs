@@ -0,0 +1,312 @@
+use crate::app::request_id::RequestId;
+use crate::utils::line_writer::LineWriter;
+use crate::utils::logger::{LoggingConfig, RotatingFile};
+use crate::utils::redact::redact_path;
+use axum::body::Body;
+use axum::extract::{ConnectInfo, Request};
+use axum::http::{Extensions, StatusCode};
+use axum::middleware::Next;
+use axum::response::Response;
+use chrono::Utc;
+use futures_util::StreamExt;
+use http_body_util::BodyExt;
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+static ACCESS_LOG: OnceCell<LineWriter> = OnceCell::new();
+
+/// Opens the JSON Lines access log, a file or `stdout`; without it nothing is
+/// recorded. A file is rotated like the log files of `logging`.
+pub fn init(path: &str, logging: &LoggingConfig) -> io::Result<()> {
+    let writer = if path == "stdout" {
+        LineWriter::spawn("access log", io::stdout())
+    } else {
+        let path = Path::new(path);
+        let file_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid file name"))?;
+        let directory = match path.parent() {
+            Some(directory) if !directory.as_os_str().is_empty() => directory.to_path_buf(),
+            _ => ".".into(),
+        };
+        let file = RotatingFile::open(
+            directory,
+            file_name,
+            logging.rotation,
+            logging.max_file_size_mb * 1024 * 1024,
+            logging.max_files,
+        )?;
+        LineWriter::spawn("access log", file)
+    };
+    let _ = ACCESS_LOG.set(writer);
+    Ok(())
+}
+
+/// What the handlers learned about a request while serving it.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AccessDetails {
+    pub network: Option<String>,
+    pub api_key_id: Option<String>,
+    /// Redacted URLs of the nodes tried, in order.
+    pub nodes: Vec<String>,
+    pub proxy: Option<String>,
+    pub attempts: usize,
+    pub upstream_status: Option<u16>,
+    /// Answered from a cache without asking a node.
+    pub cached: bool,
+    /// Served by several narrower upstream calls.
+    pub split: bool,
+    /// Request path with the segment an API key may be given in masked,
+    /// logged in place of the one received.
+    #[serde(skip)]
+    pub path: Option<String>,
+}
+
+/// Shared handle to a request's [`AccessDetails`], kept in its extensions.
+#[derive(Debug, Clone, Default)]
+pub struct AccessContext(Arc<Mutex<AccessDetails>>);
+
+impl AccessContext {
+    /// The request's context; a detached one when the access log is off.
+    pub fn from_extensions(extensions: &Extensions) -> Self {
+        extensions.get::<Self>().cloned().unwrap_or_default()
+    }
+
+    pub fn update(&self, f: impl FnOnce(&mut AccessDetails)) {
+        f(&mut self.0.lock().unwrap())
+    }
+}
+
+/// Writes one access log line per client request once its response has been
+/// sent, or abandoned by the client.
+pub async fn log_access(req: Request, next: Next) -> Response {
+    if ACCESS_LOG.get().is_none() {
+        return next.run(req).await;
+    }
+    let start = Instant::now();
+
+    let (mut parts, body) = req.into_parts();
+    let body = match body.collect().await {
+        Ok(collected) => collected.to_bytes(),
+        Err(e) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(format!("Error: {}", e)))
+                .unwrap()
+        }
+    };
+    let (methods, batch_size) = match serde_json::from_slice::<Value>(&body) {
+        Ok(Value::Array(batch)) => (batch.iter().filter_map(method).collect(), Some(batch.len())),
+        Ok(request) => (method(&request).into_iter().collect(), None),
+        Err(_) => (Vec::new(), None),
+    };
+    let context = AccessContext::default();
+    parts.extensions.insert(context.clone());
+
+    let Value::Object(mut fields) = json!({
+        "timestamp": Utc::now(),
+        "request_id": parts.extensions.get::<RequestId>().map(RequestId::as_str),
+        "client": parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(address)| address.ip().to_string()),
+        "http_method": parts.method.as_str(),
+        "path": redact_path(parts.uri.path()),
+        "methods": methods,
+        "batch_size": batch_size,
+    }) else {
+        unreachable!()
+    };
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+    fields.insert("status".to_string(), json!(response.status().as_u16()));
+
+    let mut entry = PendingEntry {
+        start,
+        fields,
+        context,
+        response_bytes: 0,
+    };
+
+    let (parts, body) = response.into_parts();
+    let body = body.into_data_stream().map(move |chunk| {
+        if let Ok(chunk) = &chunk {
+            entry.count(chunk.len());
+        }
+        chunk
+    });
+    Response::from_parts(parts, Body::from_stream(body))
+}
+
+fn method(request: &Value) -> Option<String> {
+    request
+        .get("method")
+        .and_then(Value::as_str)
+        .map(String::from)
+}
+
+/// Access log line completed and written when the response body is dropped.
+struct PendingEntry {
+    start: Instant,
+    fields: Map<String, Value>,
+    context: AccessContext,
+    response_bytes: usize,
+}
+
+impl PendingEntry {
+    fn count(&mut self, bytes: usize) {
+        self.response_bytes += bytes;
+    }
+}
+
+impl Drop for PendingEntry {
+    fn drop(&mut self) {
+        let Some(output) = ACCESS_LOG.get() else {
+            return;
+        };
+        let mut line = std::mem::take(&mut self.fields);
+        let details = self.context.0.lock().unwrap();
+        if let Some(path) = &details.path {
+            line.insert("path".to_string(), json!(redact_path(path)));
+        }
+        if let Value::Object(details) = json!(*details) {
+            line.extend(details);
+        }
+        line.insert("response_bytes".to_string(), json!(self.response_bytes));
+        line.insert(
+            "latency_ms".to_string(),
+            json!(self.start.elapsed().as_millis() as u64),
+        );
+
+        output.write(Value::Object(line).to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::auth::{require_api_key, ApiKeyConfig, ApiKeys};
+    use axum::middleware::Next;
+    use axum::routing::{any, post};
+    use axum::{middleware, Router};
+    use tower::ServiceExt;
+
+    /// The first access log line `matches` accepts, once written by the access
+    /// log's own thread.
+    async fn find_line(path: &Path, matches: impl Fn(&Value) -> bool) -> Option<Value> {
+        for _ in 0..100 {
+            let log = std::fs::read_to_string(path).unwrap_or_default();
+            let line = log
+                .lines()
+                .map(|line| serde_json::from_str::<Value>(line).unwrap())
+                .find(|line| matches(line));
+            if line.is_some() {
+                return line;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        None
+    }
+
+    #[tokio::test]
+    async fn test_access_log() {
+        let path = std::env::temp_dir().join(format!(
+            "tutus_nodus_access_{}/access.log",
+            std::process::id()
+        ));
+        init(&path.to_string_lossy(), &LoggingConfig::default()).unwrap();
+
+        let app = Router::new()
+            .route(
+                "/rpc/access-test",
+                post(|req: Request| async move {
+                    AccessContext::from_extensions(req.extensions()).update(|details| {
+                        details.network = Some("access-test".to_string());
+                        details.cached = true;
+                    });
+                    "{\"result\":1}"
+                }),
+            )
+            .layer(middleware::from_fn(log_access));
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/rpc/access-test")
+                    .body(Body::from(
+                        r#"[{"method":"getSlot"},{"method":"getBalance"}]"#,
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        response.into_body().collect().await.unwrap();
+
+        let line = find_line(&path, |line| line["path"] == "/rpc/access-test").await;
+        let line = line.unwrap();
+        assert_eq!(line["network"], "access-test");
+        assert_eq!(line["methods"], json!(["getSlot", "getBalance"]));
+        assert_eq!(line["batch_size"], 2);
+        assert_eq!(line["status"], 200);
+        assert_eq!(line["response_bytes"], 12);
+        assert_eq!(line["cached"], true);
+        assert!(line["timestamp"].is_string());
+
+        // Keys in the path are masked however short they are
+        let keys: Vec<ApiKeyConfig> = serde_json::from_str(
+            r#"[{"name": "short", "key": "change-me", "networks": ["access-key-test"]}]"#,
+        )
+        .unwrap();
+        let keys = Arc::new(ApiKeys::new(None, &keys).unwrap());
+        let app = Router::new()
+            .route("/rpc/:network/*path", any(|| async { "{}" }))
+            .layer(middleware::from_fn(move |req: Request, next: Next| {
+                require_api_key(keys.clone(), req, next)
+            }))
+            .layer(middleware::from_fn(log_access));
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/rpc/access-key-test/change-me/v1/status")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        response.into_body().collect().await.unwrap();
+
+        let line = find_line(&path, |line| line["api_key_id"] == "short").await;
+        assert_eq!(line.unwrap()["path"], "/rpc/access-key-test/***/v1/status");
+
+        // So are segments that are not a known key, such as a mistyped one
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/rpc/access-key-test/chnge-me/v1/status")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        response.into_body().collect().await.unwrap();
+
+        let line = find_line(&path, |line| line["status"] == 401).await;
+        assert_eq!(line.unwrap()["path"], "/rpc/access-key-test/***/v1/status");
+        let log = std::fs::read_to_string(&path).unwrap();
+        assert!(!log.contains("change-me"));
+        assert!(!log.contains("chnge-me"));
+    }
+}
@@ -0,0 +1,358 @@
+use crate::app::access_log::AccessContext;
+use crate::utils::error::ApiKeyError;
+use crate::utils::jsonrpc::rpc_error;
+use axum::extract::Request;
+use axum::http::header::AUTHORIZATION;
+use axum::http::uri::PathAndQuery;
+use axum::http::{HeaderName, StatusCode, Uri};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Json, Response};
+use log::{debug, warn};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::{Arc, RwLock};
+
+pub static API_KEY_HEADER: HeaderName = HeaderName::from_static("x-api-key");
+
+/// Query parameter a key may be passed in instead of a header.
+const API_KEY_PARAM: &str = "api_key";
+
+/// Network list entry allowing every network.
+const ALL_NETWORKS: &str = "*";
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// An entry of the API keys file or the `api_keys` config list.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ApiKeyConfig {
+    /// Identifies the client in logs; the key itself is never logged.
+    pub name: String,
+    pub key: String,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Networks the key may access; `*` allows all of them.
+    #[serde(default)]
+    pub networks: Vec<String>,
+}
+
+impl ApiKeyConfig {
+    fn allows(&self, network: &str) -> bool {
+        self.networks
+            .iter()
+            .any(|allowed| allowed == ALL_NETWORKS || allowed == network)
+    }
+}
+
+/// Name of the API key a request was authenticated with, kept in its extensions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiKeyName(pub String);
+
+/// Keys accepted on the RPC routes, by key. Without any configured the routes
+/// are open.
+#[derive(Debug, Default)]
+pub struct ApiKeys {
+    keys: RwLock<Option<HashMap<String, ApiKeyConfig>>>,
+}
+
+impl ApiKeys {
+    /// Keys of the JSON file at `path` overlaid by `configured`; an entry with
+    /// the same key as one of the file replaces it.
+    pub fn new(path: Option<&str>, configured: &[ApiKeyConfig]) -> Result<Self, ApiKeyError> {
+        Ok(ApiKeys {
+            keys: RwLock::new(Self::read_keys(path, configured)?),
+        })
+    }
+
+    /// Re-reads the keys, keeping the current ones if the file is invalid.
+    pub fn reload(
+        &self,
+        path: Option<&str>,
+        configured: &[ApiKeyConfig],
+    ) -> Result<(), ApiKeyError> {
+        let keys = Self::read_keys(path, configured)?;
+        *self.keys.write().unwrap() = keys;
+        Ok(())
+    }
+
+    fn read_keys(
+        path: Option<&str>,
+        configured: &[ApiKeyConfig],
+    ) -> Result<Option<HashMap<String, ApiKeyConfig>>, ApiKeyError> {
+        let mut entries = match path {
+            Some(path) => {
+                let contents = fs::read_to_string(path).map_err(ApiKeyError::ReadKeysFileError)?;
+                serde_json::from_str::<Vec<ApiKeyConfig>>(&contents)
+                    .map_err(ApiKeyError::ParseKeysFileError)?
+            }
+            None if configured.is_empty() => return Ok(None),
+            None => Vec::new(),
+        };
+        entries.extend_from_slice(configured);
+
+        debug!("Loaded {} API keys", entries.len());
+        Ok(Some(
+            entries
+                .into_iter()
+                .map(|entry| (entry.key.clone(), entry))
+                .collect(),
+        ))
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.keys.read().unwrap().is_some()
+    }
+
+    fn contains(&self, key: &str) -> bool {
+        self.keys
+            .read()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|keys| keys.contains_key(key))
+    }
+
+    /// Name of the key if it may access `network`.
+    fn authorize(&self, key: Option<&str>, network: &str) -> Result<String, Rejection> {
+        let keys = self.keys.read().unwrap();
+        let Some(key) = key else {
+            return Err(Rejection::Unauthorized("Missing API key"));
+        };
+        let Some(entry) = keys.as_ref().and_then(|keys| keys.get(key)) else {
+            return Err(Rejection::Unauthorized("Invalid API key"));
+        };
+        if !entry.enabled {
+            return Err(Rejection::Forbidden(
+                "API key is disabled".to_string(),
+                entry.name.clone(),
+            ));
+        }
+        if !entry.allows(network) {
+            return Err(Rejection::Forbidden(
+                format!("API key may not access network {}", network),
+                entry.name.clone(),
+            ));
+        }
+        Ok(entry.name.clone())
+    }
+}
+
+/// Why a request was turned away; a forbidden one carries the name of its key.
+#[derive(Debug)]
+enum Rejection {
+    Unauthorized(&'static str),
+    Forbidden(String, String),
+}
+
+impl Rejection {
+    fn key_name(&self) -> Option<&String> {
+        match self {
+            Rejection::Unauthorized(_) => None,
+            Rejection::Forbidden(_, name) => Some(name),
+        }
+    }
+}
+
+impl IntoResponse for Rejection {
+    fn into_response(self) -> Response {
+        let (status, code, message) = match &self {
+            Rejection::Unauthorized(message) => {
+                (StatusCode::UNAUTHORIZED, -32001, message.to_string())
+            }
+            Rejection::Forbidden(message, _) => (StatusCode::FORBIDDEN, -32003, message.clone()),
+        };
+        (status, Json(rpc_error(Value::Null, code, &message))).into_response()
+    }
+}
+
+/// Requires a valid API key on `/rpc/{network}` and `/ws/{network}` once keys
+/// are configured. The key is taken from the `X-Api-Key` header, an
+/// `Authorization: Bearer` header, the `api_key` query parameter or the path
+/// segment after the network, and is removed before the request is forwarded.
+pub async fn require_api_key(keys: Arc<ApiKeys>, mut req: Request, next: Next) -> Response {
+    let mut segments = req.uri().path().split('/').skip(1);
+    let network = match (segments.next(), segments.next()) {
+        (Some("rpc" | "ws"), Some(network)) if !network.is_empty() => network.to_string(),
+        _ => return next.run(req).await,
+    };
+    if !keys.is_enabled() {
+        return next.run(req).await;
+    }
+
+    let context = AccessContext::from_extensions(req.extensions());
+    // The segment may be a mistyped or revoked key, so it is never logged
+    if let Some(path) = mask_path_key(req.uri().path()) {
+        context.update(|details| details.path = Some(path));
+    }
+    let key = take_key(&keys, &mut req);
+    match keys.authorize(key.as_deref(), &network) {
+        Ok(name) => {
+            context.update(|details| details.api_key_id = Some(name.clone()));
+            req.extensions_mut().insert(ApiKeyName(name));
+            next.run(req).await
+        }
+        Err(rejection) => {
+            warn!("Rejected request for network {}: {:?}", network, rejection);
+            context.update(|details| details.api_key_id = rejection.key_name().cloned());
+            rejection.into_response()
+        }
+    }
+}
+
+/// `/rpc/{network}/{segment}[/{sub-path}]` with the segment after the network
+/// masked, where a key may have been given.
+fn mask_path_key(path: &str) -> Option<String> {
+    let mut segments: Vec<&str> = path.splitn(5, '/').collect();
+    match segments.as_slice() {
+        ["", "rpc", _, segment, ..] if !segment.is_empty() => {
+            segments[3] = "***";
+            Some(segments.join("/"))
+        }
+        _ => None,
+    }
+}
+
+/// Removes the client's key from the request so it is not sent upstream.
+fn take_key(keys: &ApiKeys, req: &mut Request) -> Option<String> {
+    let headers = req.headers_mut();
+    if let Some(key) = headers.remove(&API_KEY_HEADER) {
+        return key.to_str().ok().map(String::from);
+    }
+    let bearer = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|key| key.trim().to_string());
+    if bearer.is_some() {
+        headers.remove(AUTHORIZATION);
+        return bearer;
+    }
+
+    let uri = req.uri();
+    let mut path = uri.path().to_string();
+    let mut query: Vec<&str> = uri
+        .query()
+        .map(|query| query.split('&').collect())
+        .unwrap_or_default();
+    let mut key = None;
+    // Pairs are decoded only to be compared; the others are forwarded as sent
+    let param = query.iter().position(|pair| {
+        form_urlencoded::parse(pair.as_bytes())
+            .next()
+            .is_some_and(|(name, _)| name == API_KEY_PARAM)
+    });
+    if let Some(position) = param {
+        key = form_urlencoded::parse(query.remove(position).as_bytes())
+            .next()
+            .map(|(_, key)| key.into_owned());
+    } else if path.starts_with("/rpc/") {
+        // `/rpc/{network}/{key}[/{sub-path}]`
+        let segment = path
+            .split('/')
+            .nth(3)
+            .filter(|segment| keys.contains(segment));
+        if let Some(segment) = segment {
+            key = Some(segment.to_string());
+            let prefix = path.splitn(4, '/').take(3).collect::<Vec<_>>().join("/");
+            path = format!("{}{}", prefix, &path[prefix.len() + 1 + segment.len()..]);
+        }
+    }
+    if key.is_some() {
+        let path_and_query = match query.is_empty() {
+            true => path,
+            false => format!("{}?{}", path, query.join("&")),
+        };
+        let mut parts = uri.clone().into_parts();
+        parts.path_and_query = PathAndQuery::try_from(path_and_query).ok();
+        if let Ok(uri) = Uri::from_parts(parts) {
+            *req.uri_mut() = uri;
+        }
+    }
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::routing::{any, post};
+    use axum::{middleware, Router};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_require_api_key() {
+        let keys: Vec<ApiKeyConfig> = serde_json::from_str(
+            r#"[
+                {"name": "alice", "key": "alice-key", "networks": ["solana"]},
+                {"name": "bob", "key": "bob-key", "networks": ["*"], "enabled": false}
+            ]"#,
+        )
+        .unwrap();
+        let keys = Arc::new(ApiKeys::new(None, &keys).unwrap());
+
+        let echo = |req: Request| async move {
+            let name = req.extensions().get::<ApiKeyName>().unwrap().0.clone();
+            let leaked = req.headers().contains_key(&API_KEY_HEADER)
+                || req.headers().contains_key(AUTHORIZATION);
+            format!("{} {} {}", name, req.uri(), leaked)
+        };
+        let app = Router::new()
+            .route("/rpc/:network", post(echo))
+            .route("/rpc/:network/*path", any(echo))
+            .route("/hello", any(|| async { "Hello" }))
+            .layer(middleware::from_fn(move |req: Request, next: Next| {
+                require_api_key(keys.clone(), req, next)
+            }));
+        let call = |uri: &str, header: Option<(&str, &str)>| {
+            let mut request = Request::builder().method("POST").uri(uri);
+            if let Some((name, value)) = header {
+                request = request.header(name, value);
+            }
+            let request = request.body(Body::empty()).unwrap();
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                let status = response.status();
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+                (status, String::from_utf8(body.to_vec()).unwrap())
+            }
+        };
+
+        let (status, body) = call("/rpc/solana", Some(("x-api-key", "alice-key"))).await;
+        assert_eq!(
+            (status, body.as_str()),
+            (StatusCode::OK, "alice /rpc/solana false")
+        );
+        let (_, body) = call("/rpc/solana", Some(("authorization", "Bearer alice-key"))).await;
+        assert_eq!(body, "alice /rpc/solana false");
+        let (_, body) = call("/rpc/solana?a=1&api_key=alice-key", None).await;
+        assert_eq!(body, "alice /rpc/solana?a=1 false");
+        let (_, body) = call("/rpc/solana?api_key=alice%2Dkey&b=%2F", None).await;
+        assert_eq!(body, "alice /rpc/solana?b=%2F false");
+        let (_, body) = call("/rpc/solana/alice-key", None).await;
+        assert_eq!(body, "alice /rpc/solana false");
+        let (_, body) = call("/rpc/solana/alice-key/v1/status?b=2", None).await;
+        assert_eq!(body, "alice /rpc/solana/v1/status?b=2 false");
+
+        let (status, body) = call("/rpc/solana", None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["error"]["code"], -32001);
+        assert_eq!(body["error"]["message"], "Missing API key");
+        let (status, _) = call("/rpc/solana/unknown-key", None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, body) = call("/rpc/ethereum/alice-key", None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(body.contains("may not access network ethereum"));
+        let (status, body) = call("/rpc/solana", Some(("x-api-key", "bob-key"))).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(body.contains("disabled"));
+
+        let (status, _) = call("/hello", None).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+}
@@ -0,0 +1,453 @@
+use crate::app::access_log::AccessContext;
+use crate::app::request_id::RequestId;
+use crate::utils::jsonrpc::rpc_methods;
+use axum::body::{Body, Bytes};
+use axum::extract::Request;
+use axum::http::{HeaderMap, StatusCode};
+use axum::middleware::Next;
+use axum::response::Response;
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use http_body_util::BodyExt;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+static STORE: OnceCell<CaptureStore> = OnceCell::new();
+
+const REDACTED: &str = "***";
+
+/// Which client requests are captured with their full bodies, and how.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CaptureConfig {
+    /// Fraction of all requests captured, from 0 to 1.
+    pub sample_rate: f64,
+    /// JSON-RPC methods always captured.
+    pub methods: Vec<String>,
+    /// Networks captures are limited to; empty means all of them.
+    pub networks: Vec<String>,
+    /// Always capture failed requests: error statuses and JSON-RPC errors.
+    pub errors: bool,
+    /// Captures kept; the oldest are dropped first.
+    pub max_entries: usize,
+    /// Bodies are cut off beyond this size.
+    pub max_body_bytes: usize,
+    /// Headers whose values are replaced, compared case-insensitively.
+    pub redact_headers: Vec<String>,
+    /// JSON keys whose values are replaced at any depth of the bodies.
+    pub redact_fields: Vec<String>,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            sample_rate: 0.0,
+            methods: Vec::new(),
+            networks: Vec::new(),
+            errors: true,
+            max_entries: 200,
+            max_body_bytes: 64 * 1024,
+            redact_headers: [
+                "authorization",
+                "proxy-authorization",
+                "cookie",
+                "set-cookie",
+                "x-api-key",
+            ]
+            .map(String::from)
+            .to_vec(),
+            redact_fields: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CapturedMessage {
+    pub headers: BTreeMap<String, String>,
+    pub body: String,
+    /// Whether the body was cut off at `max_body_bytes`.
+    pub truncated: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Capture {
+    pub id: u64,
+    pub timestamp: DateTime<Utc>,
+    pub request_id: Option<String>,
+    /// Why the exchange was kept: `sampled`, `method` or `error`.
+    pub reason: &'static str,
+    pub network: Option<String>,
+    pub nodes: Vec<String>,
+    pub http_method: String,
+    pub path: String,
+    pub methods: Vec<String>,
+    pub status: u16,
+    pub upstream_status: Option<u16>,
+    pub request: CapturedMessage,
+    pub response: CapturedMessage,
+}
+
+/// Summary of a capture as listed by the admin API.
+#[derive(Debug, Clone, Serialize)]
+pub struct CaptureSummary {
+    pub id: u64,
+    pub timestamp: DateTime<Utc>,
+    pub reason: &'static str,
+    pub network: Option<String>,
+    pub methods: Vec<String>,
+    pub status: u16,
+}
+
+struct CaptureStore {
+    config: CaptureConfig,
+    entries: Mutex<VecDeque<Capture>>,
+    next_id: AtomicU64,
+}
+
+/// Turns on capturing; without it requests pass through untouched.
+pub fn init(config: CaptureConfig) {
+    let _ = STORE.set(CaptureStore {
+        config,
+        entries: Mutex::new(VecDeque::new()),
+        next_id: AtomicU64::new(1),
+    });
+}
+
+/// Captures kept, newest first.
+pub fn list() -> Vec<CaptureSummary> {
+    let Some(store) = STORE.get() else {
+        return Vec::new();
+    };
+    let entries = store.entries.lock().unwrap();
+    entries
+        .iter()
+        .rev()
+        .map(|capture| CaptureSummary {
+            id: capture.id,
+            timestamp: capture.timestamp,
+            reason: capture.reason,
+            network: capture.network.clone(),
+            methods: capture.methods.clone(),
+            status: capture.status,
+        })
+        .collect()
+}
+
+pub fn get(id: u64) -> Option<Capture> {
+    let store = STORE.get()?;
+    let entries = store.entries.lock().unwrap();
+    entries.iter().find(|capture| capture.id == id).cloned()
+}
+
+/// Records the exchange of a client request when it is sampled, calls a
+/// configured method, or fails.
+pub async fn capture_exchange(req: Request, next: Next) -> Response {
+    let Some(store) = STORE.get() else {
+        return next.run(req).await;
+    };
+    let config = &store.config;
+
+    let (mut parts, body) = req.into_parts();
+    let body = match body.collect().await {
+        Ok(collected) => collected.to_bytes(),
+        Err(e) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(format!("Error: {}", e)))
+                .unwrap()
+        }
+    };
+    let context = match parts.extensions.get::<AccessContext>() {
+        Some(context) => context.clone(),
+        None => {
+            let context = AccessContext::default();
+            parts.extensions.insert(context.clone());
+            context
+        }
+    };
+    let methods = rpc_methods(&body);
+    let reason = if methods.iter().any(|method| config.methods.contains(method)) {
+        Some("method")
+    } else if config.sample_rate > 0.0 && rand::random::<f64>() < config.sample_rate {
+        Some("sampled")
+    } else {
+        None
+    };
+    let request_id = parts
+        .extensions
+        .get::<RequestId>()
+        .map(RequestId::to_string);
+    let http_method = parts.method.to_string();
+    let path = parts.uri.path().to_string();
+
+    let request_headers = parts.headers.clone();
+    let request_body = body.clone();
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+
+    let (parts, body) = response.into_parts();
+    let mut pending = PendingCapture {
+        store,
+        reason,
+        context,
+        request_id,
+        http_method,
+        path,
+        methods,
+        request_headers,
+        request_body,
+        status: parts.status,
+        headers: parts.headers.clone(),
+        body: Vec::new(),
+        truncated: false,
+    };
+    let body = body.into_data_stream().map(move |chunk| {
+        if let Ok(chunk) = &chunk {
+            pending.push(chunk);
+        }
+        chunk
+    });
+    Response::from_parts(parts, Body::from_stream(body))
+}
+
+/// Response being streamed to the client, stored once it is complete if it
+/// qualifies.
+struct PendingCapture {
+    store: &'static CaptureStore,
+    reason: Option<&'static str>,
+    context: AccessContext,
+    request_id: Option<String>,
+    http_method: String,
+    path: String,
+    methods: Vec<String>,
+    request_headers: HeaderMap,
+    request_body: Bytes,
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Vec<u8>,
+    truncated: bool,
+}
+
+impl PendingCapture {
+    fn push(&mut self, chunk: &[u8]) {
+        let room = self.store.config.max_body_bytes - self.body.len();
+        if chunk.len() > room {
+            self.truncated = true;
+        }
+        self.body.extend_from_slice(&chunk[..chunk.len().min(room)]);
+    }
+
+    fn is_error(&self, upstream_status: Option<u16>) -> bool {
+        if !self.status.is_success() || upstream_status.is_some_and(|status| status >= 400) {
+            return true;
+        }
+        match serde_json::from_slice::<Value>(&self.body) {
+            Ok(Value::Array(batch)) => batch.iter().any(|response| response.get("error").is_some()),
+            Ok(response) => response.get("error").is_some(),
+            Err(_) => false,
+        }
+    }
+}
+
+impl Drop for PendingCapture {
+    fn drop(&mut self) {
+        let config = &self.store.config;
+        let mut details = None;
+        self.context.update(|access| {
+            details = Some((
+                access.network.clone(),
+                access.nodes.clone(),
+                access.upstream_status,
+            ))
+        });
+        let (network, nodes, upstream_status) = details.unwrap();
+
+        let network_matches = config.networks.is_empty()
+            || network
+                .as_ref()
+                .is_some_and(|network| config.networks.contains(network));
+        let reason = match self.reason {
+            Some(reason) => reason,
+            None if config.errors && self.is_error(upstream_status) => "error",
+            None => return,
+        };
+        if !network_matches {
+            return;
+        }
+
+        let mut response = capture_message(config, &self.headers, &self.body);
+        response.truncated |= self.truncated;
+        let capture = Capture {
+            id: self.store.next_id.fetch_add(1, Ordering::SeqCst),
+            timestamp: Utc::now(),
+            request_id: self.request_id.take(),
+            reason,
+            network,
+            nodes,
+            http_method: std::mem::take(&mut self.http_method),
+            path: std::mem::take(&mut self.path),
+            methods: std::mem::take(&mut self.methods),
+            status: self.status.as_u16(),
+            upstream_status,
+            request: capture_message(config, &self.request_headers, &self.request_body),
+            response,
+        };
+
+        let mut entries = self.store.entries.lock().unwrap();
+        entries.push_back(capture);
+        while entries.len() > config.max_entries {
+            entries.pop_front();
+        }
+    }
+}
+
+fn capture_message(config: &CaptureConfig, headers: &HeaderMap, body: &[u8]) -> CapturedMessage {
+    let mut captured_headers = BTreeMap::new();
+    for (name, value) in headers {
+        let value = if config
+            .redact_headers
+            .iter()
+            .any(|redacted| redacted.eq_ignore_ascii_case(name.as_str()))
+        {
+            REDACTED.to_string()
+        } else {
+            String::from_utf8_lossy(value.as_bytes()).to_string()
+        };
+        captured_headers
+            .entry(name.to_string())
+            .and_modify(|values: &mut String| {
+                values.push_str(", ");
+                values.push_str(&value);
+            })
+            .or_insert(value);
+    }
+
+    let truncated = body.len() > config.max_body_bytes;
+    let body = match serde_json::from_slice::<Value>(body) {
+        Ok(mut json) if !config.redact_fields.is_empty() => {
+            redact_fields(&mut json, &config.redact_fields);
+            json.to_string()
+        }
+        _ => String::from_utf8_lossy(body).to_string(),
+    };
+    let mut end = body.len().min(config.max_body_bytes);
+    while !body.is_char_boundary(end) {
+        end -= 1;
+    }
+    let body = body[..end].to_string();
+
+    CapturedMessage {
+        headers: captured_headers,
+        body,
+        truncated,
+    }
+}
+
+/// Replaces the values of `fields` wherever they appear as object keys.
+fn redact_fields(value: &mut Value, fields: &[String]) {
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object.iter_mut() {
+                if fields.contains(key) {
+                    *value = Value::String(REDACTED.to_string());
+                } else {
+                    redact_fields(value, fields);
+                }
+            }
+        }
+        Value::Array(values) => values
+            .iter_mut()
+            .for_each(|value| redact_fields(value, fields)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use axum::routing::post;
+    use axum::{middleware, Router};
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_redaction() {
+        let config = CaptureConfig {
+            redact_fields: vec!["secretKey".to_string()],
+            max_body_bytes: 1024,
+            ..Default::default()
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert("Authorization", HeaderValue::from_static("Bearer token"));
+        headers.insert("content-type", HeaderValue::from_static("application/json"));
+        let body = json!({"method": "sign", "params": [{"secretKey": "abc", "other": 1}]});
+
+        let message = capture_message(&config, &headers, body.to_string().as_bytes());
+        assert_eq!(message.headers["authorization"], REDACTED);
+        assert_eq!(message.headers["content-type"], "application/json");
+        let body: Value = serde_json::from_str(&message.body).unwrap();
+        assert_eq!(body["params"][0]["secretKey"], REDACTED);
+        assert_eq!(body["params"][0]["other"], 1);
+        assert!(!message.truncated);
+    }
+
+    #[tokio::test]
+    async fn test_capture_errors_and_methods() {
+        init(CaptureConfig {
+            methods: vec!["getHealth".to_string()],
+            max_entries: 2,
+            ..Default::default()
+        });
+
+        let app = Router::new()
+            .route(
+                "/rpc/capture-test",
+                post(|body: String| async move {
+                    if body.contains("getSlot") {
+                        r#"{"jsonrpc":"2.0","id":1,"result":5}"#
+                    } else {
+                        r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32000,"message":"boom"}}"#
+                    }
+                }),
+            )
+            .layer(middleware::from_fn(capture_exchange));
+        let call = |method: &str| {
+            let app = app.clone();
+            let body = json!({"jsonrpc": "2.0", "id": 1, "method": method}).to_string();
+            async move {
+                let response = app
+                    .oneshot(
+                        Request::builder()
+                            .method("POST")
+                            .uri("/rpc/capture-test")
+                            .body(Body::from(body))
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                response.into_body().collect().await.unwrap();
+            }
+        };
+
+        call("getSlot").await;
+        call("getBalance").await;
+        call("getHealth").await;
+
+        let captures = list();
+        assert_eq!(captures.len(), 2);
+        assert_eq!(captures[0].methods, vec!["getHealth"]);
+        assert_eq!(captures[0].reason, "method");
+        assert_eq!(captures[1].methods, vec!["getBalance"]);
+        assert_eq!(captures[1].reason, "error");
+
+        let capture = get(captures[1].id).unwrap();
+        assert!(capture.response.body.contains("boom"));
+        assert!(capture.request.body.contains("getBalance"));
+        assert_eq!(capture.status, 200);
+    }
+}
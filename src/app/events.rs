@@ -0,0 +1,208 @@
+use crate::app::networks::solana::transaction::TransactionSummary;
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use strum_macros::{AsRefStr, EnumString};
+use tokio::sync::broadcast::{self, Receiver, Sender};
+
+const EVENT_BUFFER: usize = 1024;
+
+static EVENTS: Lazy<Sender<EventEnvelope>> = Lazy::new(|| broadcast::channel(EVENT_BUFFER).0);
+
+/// Structured proxy activity published to the `/ws` event stream.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    RequestCompleted {
+        network: String,
+        methods: Vec<String>,
+        node: Option<String>,
+        status: u16,
+        latency_ms: u64,
+        attempts: usize,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        transactions: Vec<TransactionSummary>,
+    },
+    Retry {
+        network: String,
+        node: String,
+        attempt: usize,
+        reason: String,
+    },
+    NodeHealth {
+        network: String,
+        node: String,
+        healthy: bool,
+        reason: String,
+    },
+    ProxyQuarantine {
+        proxy: String,
+        seconds: u64,
+    },
+    Rebroadcast {
+        network: String,
+        signature: String,
+        outcome: String,
+        resends: usize,
+        duration_ms: u64,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, EnumString, AsRefStr, Deserialize)]
+#[strum(serialize_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum EventType {
+    RequestCompleted,
+    Retry,
+    NodeHealth,
+    ProxyQuarantine,
+    Rebroadcast,
+}
+
+impl Event {
+    pub fn event_type(&self) -> EventType {
+        match self {
+            Event::RequestCompleted { .. } => EventType::RequestCompleted,
+            Event::Retry { .. } => EventType::Retry,
+            Event::NodeHealth { .. } => EventType::NodeHealth,
+            Event::ProxyQuarantine { .. } => EventType::ProxyQuarantine,
+            Event::Rebroadcast { .. } => EventType::Rebroadcast,
+        }
+    }
+
+    pub fn network(&self) -> Option<&str> {
+        match self {
+            Event::RequestCompleted { network, .. }
+            | Event::Retry { network, .. }
+            | Event::NodeHealth { network, .. }
+            | Event::Rebroadcast { network, .. } => Some(network),
+            Event::ProxyQuarantine { .. } => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EventEnvelope {
+    pub timestamp: DateTime<Utc>,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+/// Publishes an event to every current subscriber; dropped when nobody listens.
+pub fn publish(event: Event) {
+    let _ = EVENTS.send(EventEnvelope {
+        timestamp: Utc::now(),
+        event,
+    });
+}
+
+pub fn subscribe() -> Receiver<EventEnvelope> {
+    EVENTS.subscribe()
+}
+
+/// Server-side filter of a subscriber; empty lists match everything.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct EventFilter {
+    #[serde(default)]
+    pub types: Vec<EventType>,
+    #[serde(default)]
+    pub networks: Vec<String>,
+}
+
+impl EventFilter {
+    /// Parses the comma separated `types` and `networks` query parameters,
+    /// failing with the names of unknown event types.
+    pub fn from_query(types: Option<&str>, networks: Option<&str>) -> Result<Self, String> {
+        let split = |list: Option<&str>| -> Vec<String> {
+            list.map(|list| {
+                list.split(',')
+                    .map(str::trim)
+                    .filter(|item| !item.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default()
+        };
+
+        let mut unknown = Vec::new();
+        let types = split(types)
+            .into_iter()
+            .filter_map(|name| match name.parse() {
+                Ok(event_type) => Some(event_type),
+                Err(_) => {
+                    unknown.push(name);
+                    None
+                }
+            })
+            .collect();
+        if !unknown.is_empty() {
+            return Err(format!("Unknown event types: {}", unknown.join(", ")));
+        }
+
+        Ok(Self {
+            types,
+            networks: split(networks),
+        })
+    }
+
+    pub fn matches(&self, event: &Event) -> bool {
+        let type_matches = self.types.is_empty() || self.types.contains(&event.event_type());
+        let network_matches = self.networks.is_empty()
+            || event
+                .network()
+                .is_some_and(|network| self.networks.iter().any(|n| n == network));
+        type_matches && network_matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_filter() {
+        let retry = Event::Retry {
+            network: "solana".to_string(),
+            node: "https://a.example/".to_string(),
+            attempt: 1,
+            reason: "429".to_string(),
+        };
+        let quarantine = Event::ProxyQuarantine {
+            proxy: "socks5://10.0.0.1:1080".to_string(),
+            seconds: 30,
+        };
+
+        assert!(EventFilter::default().matches(&retry));
+
+        let filter = EventFilter::from_query(Some("retry, node_health"), Some("solana")).unwrap();
+        assert!(filter.matches(&retry));
+        assert!(!filter.matches(&quarantine));
+
+        let filter = EventFilter::from_query(None, Some("bsc")).unwrap();
+        assert!(!filter.matches(&retry));
+
+        let filter = EventFilter::from_query(Some("proxy_quarantine"), None).unwrap();
+        assert!(filter.matches(&quarantine));
+
+        // Unknown types are rejected rather than widening the filter to everything
+        assert_eq!(
+            EventFilter::from_query(Some("retry,retries,health"), None).unwrap_err(),
+            "Unknown event types: retries, health"
+        );
+    }
+
+    #[test]
+    fn test_event_serialization() {
+        let value = serde_json::to_value(EventEnvelope {
+            timestamp: Utc::now(),
+            event: Event::ProxyQuarantine {
+                proxy: "socks5://10.0.0.1:1080".to_string(),
+                seconds: 30,
+            },
+        })
+        .unwrap();
+        assert_eq!(value["type"], "proxy_quarantine");
+        assert_eq!(value["seconds"], 30);
+        assert!(value["timestamp"].is_string());
+    }
+}
@@ -1,2 +1,10 @@
+pub mod access_log;
+pub mod auth;
+pub mod capture;
+pub mod events;
+pub mod metrics;
 pub mod networks;
+pub mod pubsub;
 pub mod query;
+pub mod rate_limit;
+pub mod request_id;
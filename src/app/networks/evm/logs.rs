@@ -0,0 +1,387 @@
+use crate::app::events::{self, Event};
+use crate::app::metrics;
+use crate::provider::proxy::Proxy;
+use crate::provider::{Network, Provider, ProxyProvider};
+use crate::utils::jsonrpc::rpc_error;
+use axum::body::Body;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::StatusCode;
+use axum::response::Response;
+use futures_util::{stream, StreamExt};
+use log::{debug, info};
+use once_cell::sync::Lazy;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Widest `eth_getLogs` block range sent to a node unless the network configures one.
+pub const DEFAULT_MAX_RANGE: u64 = 1000;
+
+/// Requests needing more sub-ranges than this are rejected instead of split.
+const MAX_CHUNKS: u64 = 1000;
+const CONCURRENCY: usize = 8;
+const MAX_ATTEMPTS: usize = 3;
+
+/// How long a range learned from a node's error is applied before the node
+/// gets to serve wider ranges again.
+const LIMIT_TTL: Duration = Duration::from_secs(600);
+
+/// Errors nodes return for a block range or result count they refuse to serve.
+const RANGE_ERRORS: [&str; 9] = [
+    "block range",
+    "range too large",
+    "range is too large",
+    "query returned more than",
+    "log response size exceeded",
+    "requested too many blocks",
+    "limited to a",
+    "exceeds max results",
+    "too many results",
+];
+
+/// Errors of nodes throttling the client, which narrower ranges do not help with.
+const RATE_LIMIT_ERRORS: [&str; 3] = ["429", "too many requests", "rate limit"];
+
+/// Ranges individual nodes turned out to accept, learned from their errors,
+/// with when they were learned.
+static NODE_LIMITS: Lazy<Mutex<HashMap<String, (u64, Instant)>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Serves an `eth_getLogs` request over a block range by splitting it into
+/// sub-ranges the network's nodes allow, fetched in parallel and narrowed
+/// further when a node rejects them. `None` when the body is anything else or
+/// asks for a single block, so it can be proxied as is.
+pub async fn split_get_logs(
+    network: Network,
+    provider: &Arc<Provider>,
+    proxy_provider: &Arc<ProxyProvider>,
+    body: &[u8],
+) -> Option<Response> {
+    let request: Value = serde_json::from_slice(body).ok()?;
+    if request["method"] != "eth_getLogs" {
+        return None;
+    }
+    let filter = request["params"][0].as_object()?;
+    if filter.contains_key("blockHash") {
+        return None;
+    }
+    let max_range = network
+        .evm_settings()
+        .get_logs_max_range
+        .unwrap_or(DEFAULT_MAX_RANGE);
+    if max_range == 0 {
+        return None;
+    }
+
+    let from = match filter.get("fromBlock").and_then(Value::as_str) {
+        Some("earliest") => 0,
+        Some(block) => parse_hex(block)?,
+        None => return None,
+    };
+    let to = match filter.get("toBlock").and_then(Value::as_str) {
+        Some("latest") | None => {
+            let url = provider.get_node_url(network).await?;
+            let request = json!({"jsonrpc": "2.0", "id": 1, "method": "eth_blockNumber"});
+            let head = Proxy::call(network, proxy_provider.clone(), &url, &request)
+                .await
+                .ok()?;
+            parse_hex(head.as_str()?)?
+        }
+        Some(block) => parse_hex(block)?,
+    };
+    if to <= from {
+        return None;
+    }
+
+    let start_time = Instant::now();
+    let id = request["id"].clone();
+    let response = if (to - from) / max_range >= MAX_CHUNKS {
+        rpc_error(id, -32005, "Block range too large")
+    } else {
+        debug!(
+            "Splitting {} eth_getLogs of blocks {}-{} into ranges of {}",
+            network, from, to, max_range
+        );
+        let calls = AtomicUsize::new(0);
+        let fetcher = Fetcher {
+            network,
+            provider,
+            proxy_provider,
+            filter: Value::Object(filter.clone()),
+            calls: &calls,
+        };
+        let ranges = (from..=to)
+            .step_by(max_range as usize)
+            .map(|start| (start, (start + max_range - 1).min(to)));
+        let results: Vec<_> = stream::iter(ranges)
+            .map(|range| fetcher.fetch(range))
+            .buffer_unordered(CONCURRENCY)
+            .collect()
+            .await;
+
+        info!(
+            "{} eth_getLogs of {} blocks took {} calls in {:?}",
+            network,
+            to - from + 1,
+            calls.load(Ordering::SeqCst),
+            start_time.elapsed()
+        );
+        let methods = vec!["eth_getLogs".to_string()];
+        let status = if results.iter().all(Result::is_ok) {
+            StatusCode::OK
+        } else {
+            StatusCode::BAD_GATEWAY
+        };
+        metrics::record_request(
+            network.as_ref(),
+            None,
+            &methods,
+            status,
+            "none",
+            start_time.elapsed(),
+        );
+        events::publish(Event::RequestCompleted {
+            network: network.to_string(),
+            methods,
+            node: None,
+            status: status.as_u16(),
+            latency_ms: start_time.elapsed().as_millis() as u64,
+            attempts: calls.load(Ordering::SeqCst),
+            transactions: Vec::new(),
+        });
+
+        match results.into_iter().collect::<Result<Vec<_>, _>>() {
+            Ok(chunks) => {
+                let mut logs: Vec<Value> = chunks.into_iter().flatten().collect();
+                logs.sort_by_key(|log| (hex_field(log, "blockNumber"), hex_field(log, "logIndex")));
+                json!({"jsonrpc": "2.0", "id": id, "result": logs})
+            }
+            Err(e) => rpc_error(id, -32603, &e),
+        }
+    };
+
+    Some(
+        Response::builder()
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(response.to_string()))
+            .unwrap(),
+    )
+}
+
+struct Fetcher<'a> {
+    network: Network,
+    provider: &'a Provider,
+    proxy_provider: &'a Arc<ProxyProvider>,
+    filter: Value,
+    calls: &'a AtomicUsize,
+}
+
+impl Fetcher<'_> {
+    /// Fetches the logs of an inclusive block range, narrowing it for nodes that
+    /// reject it as too large.
+    async fn fetch(&self, range: (u64, u64)) -> Result<Vec<Value>, String> {
+        let mut pending = vec![range];
+        let mut logs = Vec::new();
+
+        while let Some((from, to)) = pending.pop() {
+            let mut attempts = 0;
+            loop {
+                let url = self
+                    .provider
+                    .get_node_url(self.network)
+                    .await
+                    .ok_or_else(|| format!("No nodes available for {}", self.network))?;
+
+                if let Some(limit) = node_limit(&url).filter(|limit| to - from >= *limit) {
+                    pending.extend(
+                        (from..=to)
+                            .step_by(limit as usize)
+                            .map(|start| (start, (start + limit - 1).min(to))),
+                    );
+                    break;
+                }
+
+                let mut filter = self.filter.clone();
+                filter["fromBlock"] = json!(format!("0x{:x}", from));
+                filter["toBlock"] = json!(format!("0x{:x}", to));
+                let request =
+                    json!({"jsonrpc": "2.0", "id": 1, "method": "eth_getLogs", "params": [filter]});
+                self.calls.fetch_add(1, Ordering::SeqCst);
+
+                match Proxy::call(self.network, self.proxy_provider.clone(), &url, &request).await {
+                    Ok(Value::Array(result)) => {
+                        logs.extend(result);
+                        break;
+                    }
+                    Ok(_) => return Err("Invalid eth_getLogs result".to_string()),
+                    Err(e) if is_range_error(&e) && to > from => {
+                        let blocks = to - from + 1;
+                        let limit = blocks / 2;
+                        debug!(
+                            "Node rejected {} blocks, narrowing to {}: {}",
+                            blocks, limit, e
+                        );
+                        learn_limit(url, limit);
+                        let middle = from + limit - 1;
+                        pending.push((middle + 1, to));
+                        pending.push((from, middle));
+                        break;
+                    }
+                    Err(e) => {
+                        attempts += 1;
+                        if attempts >= MAX_ATTEMPTS {
+                            return Err(e);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(logs)
+    }
+}
+
+/// Widest range `url` accepts, unless it was learned more than `LIMIT_TTL` ago.
+fn node_limit(url: &str) -> Option<u64> {
+    let mut limits = NODE_LIMITS.lock().unwrap();
+    match limits.get(url) {
+        Some((limit, learned)) if learned.elapsed() < LIMIT_TTL => Some(*limit),
+        Some(_) => {
+            limits.remove(url);
+            None
+        }
+        None => None,
+    }
+}
+
+fn learn_limit(url: String, limit: u64) {
+    let mut limits = NODE_LIMITS.lock().unwrap();
+    let limit = match limits.get(&url) {
+        Some((known, learned)) if learned.elapsed() < LIMIT_TTL => limit.min(*known),
+        _ => limit,
+    };
+    limits.insert(url, (limit, Instant::now()));
+}
+
+/// Whether a node error asks for a narrower block range or fewer results.
+/// Rate limiting never does, however it is worded.
+fn is_range_error(message: &str) -> bool {
+    let message = message.to_lowercase();
+    !RATE_LIMIT_ERRORS
+        .iter()
+        .any(|pattern| message.contains(pattern))
+        && RANGE_ERRORS.iter().any(|pattern| message.contains(pattern))
+}
+
+fn parse_hex(value: &str) -> Option<u64> {
+    u64::from_str_radix(value.strip_prefix("0x")?, 16).ok()
+}
+
+fn hex_field(log: &Value, field: &str) -> u64 {
+    log[field].as_str().and_then(parse_hex).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::BodyExt;
+    use wiremock::matchers::body_partial_json;
+    use wiremock::{Mock, MockServer, Request, Respond, ResponseTemplate};
+
+    /// Returns one log per block, refusing ranges wider than `limit`.
+    struct LogsNode {
+        limit: u64,
+    }
+
+    impl Respond for LogsNode {
+        fn respond(&self, request: &Request) -> ResponseTemplate {
+            let request: Value = serde_json::from_slice(&request.body).unwrap();
+            let filter = &request["params"][0];
+            let from = parse_hex(filter["fromBlock"].as_str().unwrap()).unwrap();
+            let to = parse_hex(filter["toBlock"].as_str().unwrap()).unwrap();
+            let response = if to - from >= self.limit {
+                json!({"jsonrpc": "2.0", "id": 1, "error": {"code": -32005, "message": "block range too large"}})
+            } else {
+                let logs: Vec<Value> = (from..=to)
+                    .rev()
+                    .map(
+                        |block| json!({"blockNumber": format!("0x{:x}", block), "logIndex": "0x0"}),
+                    )
+                    .collect();
+                json!({"jsonrpc": "2.0", "id": 1, "result": logs})
+            };
+            ResponseTemplate::new(200).set_body_json(response)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_split_get_logs() {
+        let node = MockServer::start().await;
+        Mock::given(body_partial_json(json!({"method": "eth_getLogs"})))
+            .respond_with(LogsNode { limit: 400 })
+            .mount(&node)
+            .await;
+        Mock::given(body_partial_json(json!({"method": "eth_blockNumber"})))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(json!({"jsonrpc": "2.0", "id": 1, "result": "0x9c3"})),
+            )
+            .mount(&node)
+            .await;
+
+        let provider = Arc::new(Provider::from_json(json!({"bsc": [node.uri()]})));
+        let proxy_provider =
+            Arc::new(ProxyProvider::new(String::new(), false, Duration::from_secs(1)).unwrap());
+
+        let body = |from: &str, to: &str| {
+            json!({"jsonrpc": "2.0", "id": 3, "method": "eth_getLogs", "params": [{"fromBlock": from, "toBlock": to, "address": "0x0"}]})
+                .to_string()
+        };
+
+        let fetch = |body: String| {
+            let provider = provider.clone();
+            let proxy_provider = proxy_provider.clone();
+            async move {
+                let response =
+                    split_get_logs(Network::BSC, &provider, &proxy_provider, body.as_bytes())
+                        .await?;
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+                Some(serde_json::from_slice::<Value>(&body).unwrap())
+            }
+        };
+
+        // A single block is proxied unchanged
+        assert!(fetch(body("0x5", "0x5")).await.is_none());
+
+        // Within the network's range but too wide for the node
+        let response = fetch(body("0x0", "0x3e7")).await.unwrap();
+        assert_eq!(response["result"].as_array().unwrap().len(), 1000);
+        assert_eq!(node_limit(&node.uri()), Some(250));
+
+        let response = fetch(body("0x0", "latest")).await.unwrap();
+        assert_eq!(response["id"], 3);
+        let logs = response["result"].as_array().unwrap();
+        assert_eq!(logs.len(), 2500);
+        assert!(logs
+            .iter()
+            .enumerate()
+            .all(|(block, log)| hex_field(log, "blockNumber") == block as u64));
+        assert_eq!(node_limit(&node.uri()), Some(250));
+    }
+
+    #[test]
+    fn test_is_range_error() {
+        assert!(is_range_error("block range too large"));
+        assert!(is_range_error("query returned more than 10000 results"));
+        assert!(is_range_error(
+            "eth_getLogs and eth_newFilter are limited to a 10,000 blocks range"
+        ));
+        assert!(!is_range_error(
+            "Upstream error. Status: 429 Too Many Requests"
+        ));
+        assert!(!is_range_error("rate limit exceeded"));
+        assert!(!is_range_error("daily request limit exceeded"));
+        assert!(!is_range_error("header not found"));
+    }
+}
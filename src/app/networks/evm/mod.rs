@@ -0,0 +1,163 @@
+pub mod logs;
+
+use crate::app::access_log::AccessContext;
+use crate::app::networks::verify::{call_node, Verdict};
+use crate::app::pubsub::{PubSubProtocol, Session};
+use crate::app::rate_limit::SocketLimit;
+use crate::provider::proxy::Proxy;
+use crate::provider::ProxyProvider;
+use crate::provider::{Network, Provider};
+use axum::extract::ws::WebSocket;
+use axum::response::Response;
+use axum::{body::Body, extract::Request};
+use http_body_util::BodyExt;
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+pub struct Evm;
+
+impl Evm {
+    pub async fn handle_request(
+        network: Network,
+        provider: Arc<Provider>,
+        proxy_provider: Arc<ProxyProvider>,
+        req: Request<Body>,
+    ) -> Response {
+        let (parts, body) = req.into_parts();
+        let body_bytes = body.collect().await.unwrap().to_bytes();
+
+        if let Some(response) =
+            logs::split_get_logs(network, &provider, &proxy_provider, &body_bytes).await
+        {
+            AccessContext::from_extensions(&parts.extensions)
+                .update(|details| details.split = true);
+            return response;
+        }
+
+        let req = Request::from_parts(parts, Body::from(body_bytes));
+        Proxy::handle_request(network, provider, proxy_provider, req).await
+    }
+
+    pub async fn handle_socket(
+        network: Network,
+        provider: Arc<Provider>,
+        proxy_provider: Arc<ProxyProvider>,
+        socket: WebSocket,
+        limit: Option<SocketLimit>,
+    ) {
+        Session::run(
+            PubSubProtocol::Ethereum,
+            network,
+            provider,
+            proxy_provider,
+            socket,
+            limit,
+        )
+        .await
+    }
+
+    /// Compares the node's `eth_chainId` (or `net_version` where that is missing)
+    /// with the chain id the network expects.
+    pub async fn verify_node(
+        network: Network,
+        proxy_provider: &Arc<ProxyProvider>,
+        url: &str,
+        expected: u64,
+    ) -> Verdict {
+        let call = |method| call_node(network, proxy_provider, url, method, json!([]));
+        let chain_id = match call("eth_chainId").await {
+            Ok(result) => Self::parse_chain_id(&result, 16),
+            Err(error) => match call("net_version").await {
+                Ok(result) => Self::parse_chain_id(&result, 10),
+                Err(_) => return Verdict::Unreachable(error),
+            },
+        };
+
+        match chain_id {
+            Some(chain_id) if chain_id == expected => Verdict::Verified,
+            Some(chain_id) => {
+                Verdict::Mismatch(format!("Chain id {}, expected {}", chain_id, expected))
+            }
+            None => Verdict::Mismatch(format!("Unrecognized chain id, expected {}", expected)),
+        }
+    }
+
+    fn parse_chain_id(result: &Value, radix: u32) -> Option<u64> {
+        let digits = result.as_str()?;
+        let digits = match radix {
+            16 => digits.strip_prefix("0x")?,
+            _ => digits,
+        };
+        u64::from_str_radix(digits, radix).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::networks::verify::verify_nodes;
+    use std::time::Duration;
+    use wiremock::matchers::body_partial_json;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    async fn node(method: &str, result: &str) -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(body_partial_json(json!({"method": method})))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(json!({"jsonrpc": "2.0", "id": 1, "result": result})),
+            )
+            .mount(&server)
+            .await;
+        server
+    }
+
+    #[tokio::test]
+    async fn test_chain_id_verification() {
+        let bsc = node("eth_chainId", "0x38").await;
+        let testnet = node("eth_chainId", "0x61").await;
+        let legacy = node("net_version", "56").await;
+
+        let direct =
+            Arc::new(ProxyProvider::new(String::new(), false, Duration::from_secs(1)).unwrap());
+        let verify = |url: String, proxy_provider: Arc<ProxyProvider>| async move {
+            Evm::verify_node(Network::BSC, &proxy_provider, &url, 56).await
+        };
+
+        assert_eq!(verify(bsc.uri(), direct.clone()).await, Verdict::Verified);
+        assert_eq!(
+            verify(legacy.uri(), direct.clone()).await,
+            Verdict::Verified
+        );
+        assert_eq!(
+            verify(testnet.uri(), direct.clone()).await,
+            Verdict::Mismatch("Chain id 97, expected 56".to_string())
+        );
+        assert!(matches!(
+            verify("http://127.0.0.1:1".to_string(), direct.clone()).await,
+            Verdict::Unreachable(_)
+        ));
+
+        // Nodes are asked through the proxy pool: an address the host doesn't have
+        // can't reach them
+        let foreign = ProxyProvider::from_json(json!({"local_address": ["192.0.2.1"]}));
+        assert!(matches!(
+            verify(bsc.uri(), Arc::new(foreign.unwrap())).await,
+            Verdict::Unreachable(_)
+        ));
+
+        let provider = Provider::from_json(json!({"bsc": [bsc.uri(), testnet.uri()]}));
+
+        verify_nodes(&provider, &direct).await;
+        assert_eq!(
+            provider
+                .quarantined(Network::BSC)
+                .keys()
+                .collect::<Vec<_>>(),
+            vec![&testnet.uri()]
+        );
+        for _ in 0..4 {
+            assert_eq!(provider.get_node_url(Network::BSC).await, Some(bsc.uri()));
+        }
+    }
+}
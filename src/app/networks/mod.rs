@@ -1 +1,3 @@
+pub mod evm;
 pub mod solana;
+pub mod verify;
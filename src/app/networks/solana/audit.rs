@@ -0,0 +1,36 @@
+use crate::app::networks::solana::transaction::TransactionSummary;
+use crate::provider::Network;
+use crate::utils::line_writer::LineWriter;
+use chrono::Utc;
+use once_cell::sync::OnceCell;
+use serde_json::json;
+use std::fs::{self, OpenOptions};
+use std::path::Path;
+
+static AUDIT_LOG: OnceCell<LineWriter> = OnceCell::new();
+
+/// Opens the JSON Lines file transactions are audited to; without it nothing is recorded.
+pub fn init(path: &str) -> std::io::Result<()> {
+    if let Some(dir) = Path::new(path).parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let _ = AUDIT_LOG.set(LineWriter::spawn("audit log", file));
+    Ok(())
+}
+
+/// Appends one line per transaction of a request with the status it was answered with.
+pub fn record(network: Network, transactions: &[TransactionSummary], status: u16) {
+    let Some(writer) = AUDIT_LOG.get() else {
+        return;
+    };
+    for transaction in transactions {
+        let line = json!({
+            "timestamp": Utc::now(),
+            "network": network.to_string(),
+            "status": status,
+            "transaction": transaction,
+        });
+        writer.write(line.to_string());
+    }
+}
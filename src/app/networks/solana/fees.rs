@@ -0,0 +1,323 @@
+use crate::app::access_log::AccessContext;
+use crate::app::networks::verify::call_node;
+use crate::provider::{Network, Provider, ProxyProvider};
+use crate::utils::jsonrpc::rpc_error;
+use futures_util::future::join_all;
+use log::debug;
+use once_cell::sync::Lazy;
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Proxy-served method aggregating `getRecentPrioritizationFees` over the node pool.
+pub const METHOD: &str = "getPriorityFeeEstimate";
+
+pub const DEFAULT_PERCENTILES: [u8; 3] = [50, 75, 90];
+pub const DEFAULT_CACHE_MS: u64 = 2000;
+
+/// `getRecentPrioritizationFees` accepts at most this many account keys.
+const MAX_ACCOUNT_KEYS: usize = 128;
+
+type CacheKey = (Network, Vec<String>);
+type CachedFees = (Instant, Vec<u64>);
+
+/// Per-slot fees by network and account keys, with the time they were fetched.
+static CACHE: Lazy<Mutex<HashMap<CacheKey, CachedFees>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Held while a network's fees are fetched, so concurrent misses wait for one fetch
+/// instead of each asking every node.
+static REFRESHING: Lazy<Mutex<HashMap<Network, Arc<tokio::sync::Mutex<()>>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Answers a `getPriorityFeeEstimate` request:
+///
+/// `{"method": "getPriorityFeeEstimate", "params": [{"accountKeys": [...], "percentiles": [50, 90]}]}`
+///
+/// Both fields are optional. The result holds `p<N>` for every percentile, `max`,
+/// and the number of slots sampled, in micro-lamports per compute unit.
+pub async fn estimate(
+    network: Network,
+    provider: &Provider,
+    proxy_provider: &Arc<ProxyProvider>,
+    request: &Value,
+    access: &AccessContext,
+) -> Value {
+    let id = request["id"].clone();
+    let options = &request["params"][0];
+    let settings = network.solana_settings();
+
+    let mut account_keys = match options.get("accountKeys") {
+        None => Vec::new(),
+        Some(Value::Array(keys)) if keys.len() <= MAX_ACCOUNT_KEYS => {
+            match keys
+                .iter()
+                .map(|key| key.as_str().map(String::from))
+                .collect()
+            {
+                Some(keys) => keys,
+                None => return rpc_error(id, -32602, "Invalid params: accountKeys"),
+            }
+        }
+        Some(_) => return rpc_error(id, -32602, "Invalid params: accountKeys"),
+    };
+    account_keys.sort();
+    account_keys.dedup();
+
+    let percentiles: Vec<u8> = match options.get("percentiles") {
+        None => settings
+            .priority_fee_percentiles
+            .unwrap_or(DEFAULT_PERCENTILES.to_vec()),
+        Some(Value::Array(percentiles)) => {
+            match percentiles
+                .iter()
+                .map(|p| p.as_u64().filter(|p| *p <= 100).map(|p| p as u8))
+                .collect()
+            {
+                Some(percentiles) => percentiles,
+                None => return rpc_error(id, -32602, "Invalid params: percentiles"),
+            }
+        }
+        Some(_) => return rpc_error(id, -32602, "Invalid params: percentiles"),
+    };
+
+    let cache_ms = settings.priority_fee_cache_ms.unwrap_or(DEFAULT_CACHE_MS);
+    let max_age = Duration::from_millis(cache_ms);
+    let Some((fees, cached)) =
+        slot_fees(network, provider, proxy_provider, account_keys, max_age).await
+    else {
+        return rpc_error(id, -32603, "Upstream unavailable");
+    };
+    access.update(|details| details.cached = cached);
+
+    let mut result = Map::new();
+    for percentile in percentiles {
+        result.insert(
+            format!("p{}", percentile),
+            json!(percentile_of(&fees, percentile)),
+        );
+    }
+    result.insert("max".to_string(), json!(fees.last().copied().unwrap_or(0)));
+    result.insert("slots".to_string(), json!(fees.len()));
+    json!({"jsonrpc": "2.0", "id": id, "result": result})
+}
+
+/// Sorted per-slot fees, the highest any healthy node reported for each slot,
+/// and whether they came from the cache.
+async fn slot_fees(
+    network: Network,
+    provider: &Provider,
+    proxy_provider: &Arc<ProxyProvider>,
+    account_keys: Vec<String>,
+    max_age: Duration,
+) -> Option<(Vec<u64>, bool)> {
+    let key = (network, account_keys);
+    let cached = |key: &CacheKey| {
+        let cache = CACHE.lock().unwrap();
+        let (fetched, fees) = cache.get(key)?;
+        (fetched.elapsed() < max_age).then(|| fees.clone())
+    };
+    if let Some(fees) = cached(&key) {
+        return Some((fees, true));
+    }
+
+    let refreshing = REFRESHING
+        .lock()
+        .unwrap()
+        .entry(network)
+        .or_default()
+        .clone();
+    let _refreshing = refreshing.lock().await;
+    if let Some(fees) = cached(&key) {
+        return Some((fees, true));
+    }
+
+    let params = json!([key.1]);
+    let responses = join_all(provider.healthy_node_urls(network).into_iter().map(|url| {
+        let params = params.clone();
+        async move {
+            let method = "getRecentPrioritizationFees";
+            call_node(network, proxy_provider, &url, method, params).await
+        }
+    }))
+    .await;
+
+    let mut by_slot = BTreeMap::new();
+    let mut answered = false;
+    for response in responses {
+        let samples = match response {
+            Ok(Value::Array(samples)) => samples,
+            Ok(_) => continue,
+            Err(e) => {
+                debug!("Failed to fetch prioritization fees: {}", e);
+                continue;
+            }
+        };
+        answered = true;
+        for sample in samples {
+            if let (Some(slot), Some(fee)) = (
+                sample["slot"].as_u64(),
+                sample["prioritizationFee"].as_u64(),
+            ) {
+                let highest = by_slot.entry(slot).or_insert(fee);
+                *highest = (*highest).max(fee);
+            }
+        }
+    }
+    if !answered {
+        return None;
+    }
+
+    let mut fees: Vec<u64> = by_slot.into_values().collect();
+    fees.sort_unstable();
+
+    let mut cache = CACHE.lock().unwrap();
+    cache.retain(|_, (fetched, _)| fetched.elapsed() < max_age);
+    cache.insert(key, (Instant::now(), fees.clone()));
+    Some((fees, false))
+}
+
+/// Nearest-rank percentile of sorted values; 0 when there are none.
+fn percentile_of(sorted: &[u64], percentile: u8) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = (percentile as usize * sorted.len()).div_ceil(100);
+    sorted[rank.saturating_sub(1)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::body_partial_json;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    async fn node(fees: Value) -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(body_partial_json(
+            json!({"method": "getRecentPrioritizationFees"}),
+        ))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!({"jsonrpc": "2.0", "id": 1, "result": fees})),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+        server
+    }
+
+    #[test]
+    fn test_percentile_of() {
+        let fees = [0, 10, 20, 30, 40, 50, 60, 70, 80, 90];
+        assert_eq!(percentile_of(&fees, 50), 40);
+        assert_eq!(percentile_of(&fees, 90), 80);
+        assert_eq!(percentile_of(&fees, 100), 90);
+        assert_eq!(percentile_of(&fees, 0), 0);
+        assert_eq!(percentile_of(&[], 50), 0);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_misses_fetch_once() {
+        let node = MockServer::start().await;
+        Mock::given(body_partial_json(
+            json!({"method": "getRecentPrioritizationFees"}),
+        ))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!({"jsonrpc": "2.0", "id": 1, "result": [{"slot": 1, "prioritizationFee": 5}]}))
+                .set_delay(Duration::from_millis(100)),
+        )
+        .expect(1)
+        .mount(&node)
+        .await;
+
+        let proxy_provider =
+            Arc::new(ProxyProvider::new(String::new(), false, Duration::from_secs(1)).unwrap());
+        let provider = Provider::from_json(json!({"solana": [node.uri()]}));
+        let request = json!({"jsonrpc": "2.0", "id": 1, "method": METHOD});
+        let access = AccessContext::default();
+
+        let responses = join_all((0..8).map(|_| {
+            estimate(
+                Network::SOLANA,
+                &provider,
+                &proxy_provider,
+                &request,
+                &access,
+            )
+        }))
+        .await;
+        for response in responses {
+            assert_eq!(response["result"]["max"], 5);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_priority_fee_estimate() {
+        let node_a = node(json!([
+            {"slot": 1, "prioritizationFee": 100},
+            {"slot": 2, "prioritizationFee": 0},
+        ]))
+        .await;
+        let node_b = node(json!([
+            {"slot": 2, "prioritizationFee": 300},
+            {"slot": 3, "prioritizationFee": 200},
+        ]))
+        .await;
+
+        let proxy_provider =
+            Arc::new(ProxyProvider::new(String::new(), false, Duration::from_secs(1)).unwrap());
+        let provider = Provider::from_json(json!({"solana-devnet": [node_a.uri(), node_b.uri()]}));
+
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 7,
+            "method": METHOD,
+            "params": [{"accountKeys": ["Vote111111111111111111111111111111111111111"]}]
+        });
+        let access = AccessContext::default();
+        let response = estimate(
+            Network::SOLANA_DEVNET,
+            &provider,
+            &proxy_provider,
+            &request,
+            &access,
+        )
+        .await;
+        assert_eq!(
+            response["result"],
+            json!({"p50": 200, "p75": 300, "p90": 300, "max": 300, "slots": 3})
+        );
+        assert_eq!(response["id"], 7);
+
+        // Served from the cache; the mocks expect a single call each
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 8,
+            "method": METHOD,
+            "params": [{"accountKeys": ["Vote111111111111111111111111111111111111111"], "percentiles": [0]}]
+        });
+        let response = estimate(
+            Network::SOLANA_DEVNET,
+            &provider,
+            &proxy_provider,
+            &request,
+            &access,
+        )
+        .await;
+        assert_eq!(response["result"]["p0"], 100);
+        access.update(|details| assert!(details.cached));
+
+        let request = json!({"id": 9, "method": METHOD, "params": [{"percentiles": [101]}]});
+        let response = estimate(
+            Network::SOLANA_DEVNET,
+            &provider,
+            &proxy_provider,
+            &request,
+            &access,
+        )
+        .await;
+        assert_eq!(response["error"]["code"], -32602);
+    }
+}
@@ -0,0 +1,243 @@
+pub mod audit;
+pub mod fees;
+pub mod program_accounts;
+pub mod rebroadcast;
+pub mod transaction;
+
+use crate::app::access_log::AccessContext;
+use crate::app::networks::verify::{call_node, Verdict};
+use crate::app::pubsub::{PubSubProtocol, Session};
+use crate::app::rate_limit::SocketLimit;
+use crate::provider::proxy::Proxy;
+use crate::provider::ProxyProvider;
+use crate::provider::{Network, Provider};
+use axum::extract::ws::WebSocket;
+use axum::http::{header::CONTENT_TYPE, StatusCode};
+use axum::response::Response;
+use axum::{body::Body, extract::Request};
+use http_body_util::BodyExt;
+use log::debug;
+use rebroadcast::Rebroadcast;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use std::time::Duration;
+use transaction::Transaction;
+
+pub struct Solana;
+
+impl Solana {
+    pub async fn handle_request(
+        network: Network,
+        provider: Arc<Provider>,
+        proxy_provider: Arc<ProxyProvider>,
+        req: Request<Body>,
+    ) -> Response {
+        let (mut parts, body) = req.into_parts();
+        let body_bytes = body.collect().await.unwrap().to_bytes();
+
+        if let Ok(request) = serde_json::from_slice::<Value>(&body_bytes) {
+            if request["method"] == fees::METHOD {
+                let access = AccessContext::from_extensions(&parts.extensions);
+                let response =
+                    fees::estimate(network, &provider, &proxy_provider, &request, &access).await;
+                return Response::builder()
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(Body::from(response.to_string()))
+                    .unwrap();
+            }
+            if request["method"] == program_accounts::METHOD {
+                let settings = network.solana_settings();
+                return program_accounts::handle(
+                    network,
+                    &settings,
+                    provider,
+                    proxy_provider,
+                    parts,
+                    body_bytes,
+                    &request,
+                )
+                .await;
+            }
+        }
+
+        // Transactions are only read; the body is forwarded unchanged
+        let transactions = Transaction::inspect(&body_bytes);
+        let settings = network.solana_settings();
+        let rebroadcast = settings.rebroadcast_interval_ms.and_then(|interval| {
+            Rebroadcast::from_request(
+                network,
+                provider.clone(),
+                proxy_provider.clone(),
+                &body_bytes,
+                Duration::from_millis(interval),
+            )
+        });
+        if !transactions.is_empty() {
+            parts.extensions.insert(transactions.clone());
+        }
+
+        let req = Request::from_parts(parts, Body::from(body_bytes));
+        let response = Proxy::handle_request(network, provider, proxy_provider, req).await;
+        audit::record(network, &transactions, response.status().as_u16());
+
+        let Some(rebroadcast) = rebroadcast else {
+            return response;
+        };
+        if !response.status().is_success() {
+            return response;
+        }
+
+        // Only transactions the node accepted are worth sending again
+        let (parts, body) = response.into_parts();
+        let body_bytes = match body.collect().await {
+            Ok(collected) => collected.to_bytes(),
+            Err(e) => {
+                return Proxy::error_response(StatusCode::BAD_GATEWAY, format!("Error: {}", e))
+            }
+        };
+        let accepted = serde_json::from_slice::<Value>(&body_bytes)
+            .is_ok_and(|response| response["result"] == rebroadcast.signature());
+        if accepted {
+            let signature = rebroadcast.signature().to_string();
+            if rebroadcast.spawn() {
+                debug!("Rebroadcasting transaction {}", signature);
+            }
+        }
+        Response::from_parts(parts, Body::from(body_bytes))
+    }
+
+    pub async fn handle_socket(
+        network: Network,
+        provider: Arc<Provider>,
+        proxy_provider: Arc<ProxyProvider>,
+        socket: WebSocket,
+        limit: Option<SocketLimit>,
+    ) {
+        Session::run(
+            PubSubProtocol::Solana,
+            network,
+            provider,
+            proxy_provider,
+            socket,
+            limit,
+        )
+        .await
+    }
+
+    /// Compares the node's `getGenesisHash` with the cluster's and, when a minimum
+    /// is configured, its `getVersion` with the oldest accepted release.
+    pub async fn verify_node(
+        network: Network,
+        proxy_provider: &Arc<ProxyProvider>,
+        url: &str,
+        genesis_hash: Option<&str>,
+        min_version: Option<&str>,
+    ) -> Verdict {
+        let call = |method| call_node(network, proxy_provider, url, method, json!([]));
+        if let Some(expected) = genesis_hash {
+            match call("getGenesisHash").await {
+                Ok(result) if result.as_str() == Some(expected) => (),
+                Ok(result) => {
+                    return Verdict::Mismatch(format!(
+                        "Genesis hash {}, expected {}",
+                        result.as_str().unwrap_or("unknown"),
+                        expected
+                    ))
+                }
+                Err(error) => return Verdict::Unreachable(error),
+            }
+        }
+
+        if let Some(min_version) = min_version {
+            let version = match call("getVersion").await {
+                Ok(result) => result["solana-core"]
+                    .as_str()
+                    .unwrap_or("unknown")
+                    .to_string(),
+                Err(error) => return Verdict::Unreachable(error),
+            };
+            if Self::parse_version(&version) < Self::parse_version(min_version) {
+                return Verdict::Mismatch(format!(
+                    "Version {}, expected at least {}",
+                    version, min_version
+                ));
+            }
+        }
+
+        Verdict::Verified
+    }
+
+    fn parse_version(version: &str) -> Vec<u64> {
+        version
+            .split('.')
+            .map_while(|part| part.parse().ok())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::body_partial_json;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    async fn node(genesis_hash: &str, version: &str) -> MockServer {
+        let server = MockServer::start().await;
+        for (method, result) in [
+            ("getGenesisHash", json!(genesis_hash)),
+            (
+                "getVersion",
+                json!({"solana-core": version, "feature-set": 1}),
+            ),
+        ] {
+            Mock::given(body_partial_json(json!({"method": method})))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .set_body_json(json!({"jsonrpc": "2.0", "id": 1, "result": result})),
+                )
+                .mount(&server)
+                .await;
+        }
+        server
+    }
+
+    #[tokio::test]
+    async fn test_cluster_verification() {
+        let mainnet = "5eykt4UsFv8P8NJdTREpY1vzqKqZKvdpKuc147dw2N9d";
+        let devnet = "EtWTRABZaYq6iMfeYKouRu166VU2xqa1wcaWoxPkrZBG";
+        let node_a = node(mainnet, "1.18.22").await;
+        let node_b = node(devnet, "2.0.3").await;
+        let proxy_provider =
+            Arc::new(ProxyProvider::new(String::new(), false, Duration::from_secs(1)).unwrap());
+        let verify = |url: String, genesis_hash, min_version| {
+            let proxy_provider = proxy_provider.clone();
+            async move {
+                Solana::verify_node(
+                    Network::SOLANA,
+                    &proxy_provider,
+                    &url,
+                    genesis_hash,
+                    min_version,
+                )
+                .await
+            }
+        };
+
+        assert_eq!(
+            verify(node_a.uri(), Some(mainnet), Some("1.18.0")).await,
+            Verdict::Verified
+        );
+        assert_eq!(
+            verify(node_b.uri(), Some(mainnet), None).await,
+            Verdict::Mismatch(format!("Genesis hash {}, expected {}", devnet, mainnet))
+        );
+        assert_eq!(
+            verify(node_a.uri(), Some(mainnet), Some("1.18.23")).await,
+            Verdict::Mismatch("Version 1.18.22, expected at least 1.18.23".to_string())
+        );
+        assert_eq!(
+            verify(node_b.uri(), None, Some("1.18.23")).await,
+            Verdict::Verified
+        );
+    }
+}
@@ -0,0 +1,481 @@
+use crate::app::access_log::AccessContext;
+use crate::provider::proxy::{Proxy, Upstream};
+use crate::provider::{Network, NodePool, Provider, ProxyProvider, SolanaSettings};
+use axum::body::Body;
+use axum::http::header::{ACCEPT_ENCODING, CONTENT_TYPE};
+use axum::http::request::Parts;
+use axum::response::Response;
+use bytes::Bytes;
+use futures_util::{stream, StreamExt};
+use log::{debug, warn};
+use once_cell::sync::Lazy;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+pub const METHOD: &str = "getProgramAccounts";
+
+pub const DEFAULT_TIMEOUT_SECS: u64 = 300;
+pub const DEFAULT_CONCURRENCY: usize = 4;
+pub const DEFAULT_CACHE_MAX_BYTES: usize = 256 * 1024 * 1024;
+pub const DEFAULT_CACHE_TOTAL_BYTES: usize = 1024 * 1024 * 1024;
+
+/// Program id and filters, as the request's canonical `params`.
+type CacheKey = (Network, String);
+
+struct CachedResult {
+    fetched: Instant,
+    /// When the result was last served, to evict the least recently used first.
+    used: Instant,
+    /// Serialized `result` of the response, reused under any request id.
+    result: Bytes,
+}
+
+static CACHE: Lazy<Mutex<HashMap<CacheKey, CachedResult>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Concurrency limit of a network's calls.
+struct Limit {
+    size: usize,
+    semaphore: Arc<Semaphore>,
+    /// Permits still to be retired after the limit was lowered while they were held.
+    owed: usize,
+}
+
+static SEMAPHORES: Lazy<Mutex<HashMap<Network, Limit>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Serves a `getProgramAccounts` call from the network's dedicated node pool
+/// with a long timeout, a limit on concurrent calls, and the response streamed
+/// to the client as it arrives. With a cache configured, identical calls within
+/// its lifetime are answered without asking a node.
+pub async fn handle(
+    network: Network,
+    settings: &SolanaSettings,
+    provider: Arc<Provider>,
+    proxy_provider: Arc<ProxyProvider>,
+    mut parts: Parts,
+    body: Bytes,
+    request: &Value,
+) -> Response {
+    let cache_ttl = settings
+        .program_accounts_cache_secs
+        .filter(|secs| *secs > 0)
+        .map(Duration::from_secs);
+    let key = (network, request["params"].to_string());
+
+    if let Some(ttl) = cache_ttl {
+        if let Some(result) = cached_result(&key, ttl) {
+            debug!("Serving {} {} from cache", network, METHOD);
+            AccessContext::from_extensions(&parts.extensions)
+                .update(|details| details.cached = true);
+            return cached_response(&request["id"], result);
+        }
+    }
+
+    let concurrency = settings
+        .program_accounts_concurrency
+        .unwrap_or(DEFAULT_CONCURRENCY)
+        .max(1);
+    let permit = semaphore(network, concurrency)
+        .acquire_owned()
+        .await
+        .unwrap();
+
+    let upstream = Upstream {
+        pool: NodePool::ProgramAccounts,
+        timeout: Duration::from_secs(
+            settings
+                .program_accounts_timeout_secs
+                .unwrap_or(DEFAULT_TIMEOUT_SECS),
+        ),
+        ..Default::default()
+    };
+    // A compressed response would be streamed through unchanged and couldn't be cached
+    if cache_ttl.is_some() {
+        parts.headers.remove(ACCEPT_ENCODING);
+    }
+    let req = axum::http::Request::from_parts(parts, Body::from(body));
+    let response = Proxy::forward(network, provider, proxy_provider, req, upstream).await;
+
+    let (parts, body) = response.into_parts();
+    let capture = match cache_ttl {
+        Some(ttl) if parts.status.is_success() => Some(Capture {
+            key,
+            ttl,
+            max_bytes: settings
+                .program_accounts_cache_max_bytes
+                .unwrap_or(DEFAULT_CACHE_MAX_BYTES),
+            total_bytes: settings
+                .program_accounts_cache_total_bytes
+                .unwrap_or(DEFAULT_CACHE_TOTAL_BYTES),
+            buffer: Vec::new(),
+        }),
+        _ => None,
+    };
+    Response::from_parts(parts, stream_body(body, permit, capture))
+}
+
+/// Result cached for `key` within `ttl`; an expired one is dropped.
+fn cached_result(key: &CacheKey, ttl: Duration) -> Option<Bytes> {
+    let mut cache = CACHE.lock().unwrap();
+    let cached = cache.get_mut(key)?;
+    if cached.fetched.elapsed() >= ttl {
+        cache.remove(key);
+        return None;
+    }
+    cached.used = Instant::now();
+    Some(cached.result.clone())
+}
+
+/// Semaphore of the network's calls, resized to a reloaded limit so calls in
+/// flight keep counting against it.
+fn semaphore(network: Network, concurrency: usize) -> Arc<Semaphore> {
+    let mut semaphores = SEMAPHORES.lock().unwrap();
+    let limit = semaphores.entry(network).or_insert_with(|| Limit {
+        size: concurrency,
+        semaphore: Arc::new(Semaphore::new(concurrency)),
+        owed: 0,
+    });
+    if concurrency > limit.size {
+        let added = concurrency - limit.size;
+        let repaid = added.min(limit.owed);
+        limit.owed -= repaid;
+        limit.semaphore.add_permits(added - repaid);
+    } else {
+        limit.owed += limit.size - concurrency;
+    }
+    limit.size = concurrency;
+    // Permits held when the limit was lowered are retired as their calls finish
+    if limit.owed > 0 {
+        limit.owed -= limit.semaphore.forget_permits(limit.owed);
+    }
+    limit.semaphore.clone()
+}
+
+/// Copy of a streamed response kept for the cache, unless it grows too large.
+struct Capture {
+    key: CacheKey,
+    ttl: Duration,
+    max_bytes: usize,
+    /// Budget of all results cached for the network.
+    total_bytes: usize,
+    buffer: Vec<u8>,
+}
+
+impl Capture {
+    fn push(mut self, chunk: &[u8]) -> Option<Self> {
+        if self.buffer.len() + chunk.len() > self.max_bytes {
+            debug!("{} response too large to cache", METHOD);
+            return None;
+        }
+        self.buffer.extend_from_slice(chunk);
+        Some(self)
+    }
+
+    /// Parses the complete response off the async runtime and caches its result.
+    fn store(self) {
+        tokio::task::spawn_blocking(move || {
+            if let Some(result) = response_result(&self.buffer) {
+                insert(self.key, result, self.ttl, self.total_bytes);
+            }
+        });
+    }
+}
+
+/// Serialized `result` of a successful JSON-RPC response.
+fn response_result(response: &[u8]) -> Option<Bytes> {
+    match serde_json::from_slice::<Value>(response) {
+        Ok(Value::Object(mut response)) if !response.contains_key("error") => response
+            .remove("result")
+            .map(|result| Bytes::from(result.to_string())),
+        _ => None,
+    }
+}
+
+/// Caches `result`, evicting expired results and then the network's least
+/// recently used ones until it fits in `total_bytes`.
+fn insert(key: CacheKey, result: Bytes, ttl: Duration, total_bytes: usize) {
+    if result.len() > total_bytes {
+        debug!("{} response exceeds the cache budget", METHOD);
+        return;
+    }
+
+    let network = key.0;
+    let mut cache = CACHE.lock().unwrap();
+    cache.retain(|(cached_network, _), cached| {
+        *cached_network != network || cached.fetched.elapsed() < ttl
+    });
+    cache.remove(&key);
+    let mut cached_bytes: usize = cache
+        .iter()
+        .filter(|((cached_network, _), _)| *cached_network == network)
+        .map(|(_, cached)| cached.result.len())
+        .sum();
+    // Evict the network's least recently used results to fit the new one
+    while cached_bytes + result.len() > total_bytes {
+        let Some(oldest) = cache
+            .iter()
+            .filter(|((cached_network, _), _)| *cached_network == network)
+            .min_by_key(|(_, cached)| cached.used)
+            .map(|(key, _)| key.clone())
+        else {
+            break;
+        };
+        if let Some(evicted) = cache.remove(&oldest) {
+            cached_bytes -= evicted.result.len();
+        }
+    }
+    let now = Instant::now();
+    cache.insert(
+        key,
+        CachedResult {
+            fetched: now,
+            used: now,
+            result,
+        },
+    );
+}
+
+/// Passes the upstream body through chunk by chunk, holding the concurrency
+/// permit until it has been fully sent.
+fn stream_body(body: Body, permit: OwnedSemaphorePermit, capture: Option<Capture>) -> Body {
+    let state = Some((body.into_data_stream(), permit, capture));
+    Body::from_stream(stream::unfold(state, |state| async move {
+        let (mut data, permit, capture) = state?;
+        match data.next().await {
+            Some(Ok(chunk)) => {
+                let capture = capture.and_then(|capture| capture.push(&chunk));
+                Some((Ok(chunk), Some((data, permit, capture))))
+            }
+            Some(Err(e)) => {
+                warn!("{} response stream failed: {}", METHOD, e);
+                Some((Err(e), None))
+            }
+            None => {
+                if let Some(capture) = capture {
+                    capture.store();
+                }
+                None
+            }
+        }
+    }))
+}
+
+fn cached_response(id: &Value, result: Bytes) -> Response {
+    let prefix = Bytes::from(format!(r#"{{"jsonrpc":"2.0","id":{},"result":"#, id));
+    let chunks = [prefix, result, Bytes::from_static(b"}")];
+    Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from_stream(stream::iter(
+            chunks.map(Ok::<_, std::io::Error>),
+        )))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::BodyExt;
+    use serde_json::json;
+    use wiremock::matchers::body_partial_json;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_program_accounts_pool_and_cache() {
+        let default_node = MockServer::start().await;
+        let gpa_node = MockServer::start().await;
+        Mock::given(body_partial_json(json!({"method": METHOD})))
+            .respond_with(ResponseTemplate::new(200).set_body_json(
+                json!({"jsonrpc": "2.0", "id": 1, "result": [{"pubkey": "A", "account": {}}]}),
+            ))
+            .expect(1)
+            .mount(&gpa_node)
+            .await;
+
+        let network = Network::SOLANA_DEVNET;
+        let settings = SolanaSettings {
+            program_accounts_cache_secs: Some(60),
+            ..Default::default()
+        };
+
+        let provider = Arc::new(Provider::from_json(
+            json!({"solana-devnet": {"http": [default_node.uri()], "program_accounts": [gpa_node.uri()]}}),
+        ));
+        let proxy_provider =
+            Arc::new(ProxyProvider::new(String::new(), false, Duration::from_secs(1)).unwrap());
+
+        let params = json!(["Program1", {"encoding": "base64"}]);
+        let key = || (network, params.to_string());
+        let call = |id: u64| {
+            let provider = provider.clone();
+            let proxy_provider = proxy_provider.clone();
+            let settings = settings.clone();
+            let params = params.clone();
+            async move {
+                let request =
+                    json!({"jsonrpc": "2.0", "id": id, "method": METHOD, "params": params});
+                let body = Bytes::from(request.to_string());
+                let (parts, _) = axum::http::Request::new(()).into_parts();
+                let response = handle(
+                    network,
+                    &settings,
+                    provider,
+                    proxy_provider,
+                    parts,
+                    body,
+                    &request,
+                )
+                .await;
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+                serde_json::from_slice::<Value>(&body).unwrap()
+            }
+        };
+
+        let response = call(1).await;
+        assert_eq!(response["result"][0]["pubkey"], "A");
+
+        // The stream caches its result in the background; storing it here makes the
+        // second call find it regardless. The node expects a single call
+        let result = response_result(response.to_string().as_bytes()).unwrap();
+        insert(
+            key(),
+            result,
+            Duration::from_secs(60),
+            DEFAULT_CACHE_TOTAL_BYTES,
+        );
+        let response = call(2).await;
+        assert_eq!(response["id"], 2);
+        assert_eq!(response["result"][0]["pubkey"], "A");
+    }
+
+    #[tokio::test]
+    async fn test_compressible_response_is_cached() {
+        use base64::Engine;
+        use wiremock::matchers::header_exists;
+
+        // {"jsonrpc":"2.0","id":1,"result":[{"pubkey":"Z","account":{}}]}, gzipped
+        let gzipped = base64::engine::general_purpose::STANDARD
+            .decode("H4sIAAAAAAACA6tWyirOzysqSFayUjLSM1DSUcpMUbIy1FEqSi0uzSlRsoquViooTcpOrQQqiAJKJyYn55fmASWqa2tjawGuSp9fPwAAAA==")
+            .unwrap();
+        let node = MockServer::start().await;
+        Mock::given(header_exists("accept-encoding"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("content-encoding", "gzip")
+                    .set_body_raw(gzipped, "application/json"),
+            )
+            .mount(&node)
+            .await;
+        Mock::given(body_partial_json(json!({"method": METHOD})))
+            .respond_with(ResponseTemplate::new(200).set_body_json(
+                json!({"jsonrpc": "2.0", "id": 1, "result": [{"pubkey": "Z", "account": {}}]}),
+            ))
+            .expect(1)
+            .mount(&node)
+            .await;
+
+        let network = Network::BSC_TESTNET;
+        let settings = SolanaSettings {
+            program_accounts_cache_secs: Some(60),
+            ..Default::default()
+        };
+        let provider = Arc::new(Provider::from_json(json!({"bsc-testnet": [node.uri()]})));
+        let proxy_provider =
+            Arc::new(ProxyProvider::new(String::new(), false, Duration::from_secs(1)).unwrap());
+
+        let call = |id: u64| {
+            let provider = provider.clone();
+            let proxy_provider = proxy_provider.clone();
+            let settings = settings.clone();
+            async move {
+                let request =
+                    json!({"jsonrpc": "2.0", "id": id, "method": METHOD, "params": ["Program2"]});
+                let (parts, _) = axum::http::Request::builder()
+                    .header(ACCEPT_ENCODING, "gzip, br")
+                    .body(())
+                    .unwrap()
+                    .into_parts();
+                let body = Bytes::from(request.to_string());
+                let response = handle(
+                    network,
+                    &settings,
+                    provider,
+                    proxy_provider,
+                    parts,
+                    body,
+                    &request,
+                )
+                .await;
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+                serde_json::from_slice::<Value>(&body).unwrap()
+            }
+        };
+
+        // Asked for uncompressed, the response streamed to the client can be cached
+        let response = call(1).await;
+        assert_eq!(response["result"][0]["pubkey"], "Z");
+        let result = response_result(response.to_string().as_bytes()).unwrap();
+        let key = (network, json!(["Program2"]).to_string());
+        insert(
+            key,
+            result,
+            Duration::from_secs(60),
+            DEFAULT_CACHE_TOTAL_BYTES,
+        );
+        let response = call(2).await;
+        assert_eq!(response["id"], 2);
+        assert_eq!(response["result"][0]["pubkey"], "Z");
+    }
+
+    #[tokio::test]
+    async fn test_semaphore_resize() {
+        let network = Network::ETHEREUM;
+        let first = semaphore(network, 2).acquire_owned().await.unwrap();
+        let second = semaphore(network, 2).acquire_owned().await.unwrap();
+
+        // Lowering the limit retires permits as the calls holding them finish
+        assert_eq!(semaphore(network, 1).available_permits(), 0);
+        drop(first);
+        assert_eq!(semaphore(network, 1).available_permits(), 0);
+        drop(second);
+        assert_eq!(semaphore(network, 1).available_permits(), 1);
+
+        let raised = semaphore(network, 3);
+        assert_eq!(raised.available_permits(), 3);
+        assert!(Arc::ptr_eq(&raised, &semaphore(network, 3)));
+    }
+
+    #[test]
+    fn test_response_result() {
+        let result = response_result(br#"{"jsonrpc":"2.0","id":1,"result":[1, 2]}"#);
+        assert_eq!(result, Some(Bytes::from("[1,2]")));
+        assert!(response_result(br#"{"jsonrpc":"2.0","id":1,"error":{"code":-32000}}"#).is_none());
+        assert!(response_result(br#"{"jsonrpc":"2.0","id":1,"res"#).is_none());
+    }
+
+    #[test]
+    fn test_cache_budget() {
+        let network = Network::SOLANA;
+        let ttl = Duration::from_secs(60);
+        let key = |program: &str| (network, program.to_string());
+        let result = |len: usize| Bytes::from(vec![b'0'; len]);
+        let cached = |program: &str| CACHE.lock().unwrap().contains_key(&key(program));
+
+        insert(key("A"), result(40), ttl, 100);
+        insert(key("B"), result(40), ttl, 100);
+        // A was used more recently than B, so B makes room for C
+        assert!(cached_result(&key("A"), ttl).is_some());
+        insert(key("C"), result(40), ttl, 100);
+        assert!(cached("A") && !cached("B") && cached("C"));
+
+        // Larger than the whole budget
+        insert(key("D"), result(101), ttl, 100);
+        assert!(!cached("D"));
+
+        // Expired results are dropped on lookup
+        assert!(cached_result(&key("A"), Duration::ZERO).is_none());
+        assert!(!cached("A"));
+    }
+}
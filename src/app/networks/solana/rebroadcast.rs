@@ -0,0 +1,336 @@
+use crate::app::events::{self, Event};
+use crate::app::networks::solana::transaction::{Encoding, Transaction};
+use crate::app::networks::verify::call_node;
+use crate::provider::{Network, Provider, ProxyProvider};
+use futures_util::future::join_all;
+use log::{debug, info, warn};
+use once_cell::sync::Lazy;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use strum_macros::Display;
+
+/// Upper bound on rebroadcasting, in case nodes stop answering status queries.
+/// Blockhashes expire after 150 blocks, roughly a minute.
+const MAX_REBROADCAST_DURATION: Duration = Duration::from_secs(180);
+/// Transactions of one network rebroadcast at once; further ones are only sent once.
+const MAX_CONCURRENT_REBROADCASTS: usize = 256;
+
+/// Signatures being rebroadcast, by network.
+static IN_FLIGHT: Lazy<Mutex<HashMap<Network, HashSet<String>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// A signature's place in [`IN_FLIGHT`], given up when dropped.
+struct Claim {
+    network: Network,
+    signature: String,
+}
+
+impl Claim {
+    /// `None` if the signature is already being rebroadcast or the network is at its limit.
+    fn new(network: Network, signature: &str) -> Option<Self> {
+        let mut in_flight = IN_FLIGHT.lock().unwrap();
+        let signatures = in_flight.entry(network).or_default();
+        if signatures.contains(signature) {
+            debug!("Transaction {} is already being rebroadcast", signature);
+            return None;
+        }
+        if signatures.len() >= MAX_CONCURRENT_REBROADCASTS {
+            warn!(
+                "Not rebroadcasting {}: {} transactions of {} are already being rebroadcast",
+                signature,
+                signatures.len(),
+                network
+            );
+            return None;
+        }
+        signatures.insert(signature.to_string());
+        Some(Self {
+            network,
+            signature: signature.to_string(),
+        })
+    }
+}
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        let mut in_flight = IN_FLIGHT.lock().unwrap();
+        if let Some(signatures) = in_flight.get_mut(&self.network) {
+            signatures.remove(&self.signature);
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Display)]
+#[strum(serialize_all = "snake_case")]
+pub enum Outcome {
+    Confirmed,
+    Failed,
+    Expired,
+    Abandoned,
+}
+
+/// Re-sends a signed transaction to every healthy node until it lands or expires.
+pub struct Rebroadcast {
+    network: Network,
+    provider: Arc<Provider>,
+    proxy_provider: Arc<ProxyProvider>,
+    /// The transaction exactly as the client encoded it.
+    transaction: String,
+    encoding: Encoding,
+    signature: String,
+    recent_blockhash: String,
+    interval: Duration,
+}
+
+impl Rebroadcast {
+    /// Prepares a rebroadcast of a single `sendTransaction` request; `None` for
+    /// anything else.
+    pub fn from_request(
+        network: Network,
+        provider: Arc<Provider>,
+        proxy_provider: Arc<ProxyProvider>,
+        body: &[u8],
+        interval: Duration,
+    ) -> Option<Self> {
+        let request: Value = serde_json::from_slice(body).ok()?;
+        if request["method"] != "sendTransaction" {
+            return None;
+        }
+        let transaction = request["params"][0].as_str()?.to_string();
+        let encoding = Encoding::from_params(&request["params"]);
+        let decoded = match Transaction::decode(&transaction, encoding) {
+            Ok(decoded) => decoded,
+            Err(e) => {
+                debug!("Not rebroadcasting undecodable transaction: {}", e);
+                return None;
+            }
+        };
+
+        Some(Self {
+            network,
+            provider,
+            proxy_provider,
+            signature: decoded.signature()?,
+            recent_blockhash: decoded.recent_blockhash(),
+            transaction,
+            encoding,
+            interval,
+        })
+    }
+
+    pub fn signature(&self) -> &str {
+        &self.signature
+    }
+
+    /// Rebroadcasts in the background unless the same transaction already is or the
+    /// network is at its limit; returns whether it started.
+    pub fn spawn(self) -> bool {
+        let Some(claim) = Claim::new(self.network, &self.signature) else {
+            return false;
+        };
+        tokio::spawn(async move {
+            self.run().await;
+            drop(claim);
+        });
+        true
+    }
+
+    /// Runs until the transaction is confirmed, fails, or its blockhash expires,
+    /// then records the outcome.
+    pub async fn run(self) {
+        let start_time = Instant::now();
+        let mut resends = 0;
+        let outcome = loop {
+            tokio::time::sleep(self.interval).await;
+
+            if let Some(outcome) = self.status().await {
+                break outcome;
+            }
+            if self.is_expired().await {
+                // It may still have landed in the last interval
+                break self.status().await.unwrap_or(Outcome::Expired);
+            }
+            if start_time.elapsed() > MAX_REBROADCAST_DURATION {
+                break Outcome::Abandoned;
+            }
+
+            self.resend().await;
+            resends += 1;
+        };
+
+        match outcome {
+            Outcome::Confirmed => info!(
+                "Transaction {} confirmed after {} resends",
+                self.signature, resends
+            ),
+            _ => warn!(
+                "Transaction {} {} after {} resends",
+                self.signature, outcome, resends
+            ),
+        }
+        events::publish(Event::Rebroadcast {
+            network: self.network.to_string(),
+            signature: self.signature.clone(),
+            outcome: outcome.to_string(),
+            resends,
+            duration_ms: start_time.elapsed().as_millis() as u64,
+        });
+    }
+
+    /// `None` while the transaction is unknown or only processed.
+    async fn status(&self) -> Option<Outcome> {
+        let url = self.provider.get_node_url(self.network).await?;
+        let params = json!([[self.signature], {"searchTransactionHistory": false}]);
+        let result = self.call(&url, "getSignatureStatuses", params).await.ok()?;
+        let status = &result["value"][0];
+        if !status["err"].is_null() {
+            return Some(Outcome::Failed);
+        }
+        match status["confirmationStatus"].as_str() {
+            Some("confirmed") | Some("finalized") => Some(Outcome::Confirmed),
+            _ => None,
+        }
+    }
+
+    async fn is_expired(&self) -> bool {
+        let Some(url) = self.provider.get_node_url(self.network).await else {
+            return false;
+        };
+        let params = json!([self.recent_blockhash, {"commitment": "processed"}]);
+        match self.call(&url, "isBlockhashValid", params).await {
+            Ok(result) => result["value"] == false,
+            Err(_) => false,
+        }
+    }
+
+    async fn call(&self, url: &str, method: &str, params: Value) -> Result<Value, String> {
+        call_node(self.network, &self.proxy_provider, url, method, params).await
+    }
+
+    async fn resend(&self) {
+        let encoding = match self.encoding {
+            Encoding::Base58 => "base58",
+            Encoding::Base64 => "base64",
+        };
+        let params = json!([
+            self.transaction,
+            {"encoding": encoding, "skipPreflight": true, "maxRetries": 0}
+        ]);
+        let nodes = self.provider.healthy_node_urls(self.network);
+
+        join_all(nodes.into_iter().map(|url| {
+            let params = params.clone();
+            async move {
+                if let Err(e) = self.call(&url, "sendTransaction", params).await {
+                    debug!("Rebroadcast of {} failed: {}", self.signature, e);
+                }
+            }
+        }))
+        .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::networks::solana::transaction::tests::sample_transaction;
+    use wiremock::matchers::body_partial_json;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_rebroadcast_until_confirmed() {
+        let node = MockServer::start().await;
+        let respond = |result: Value| {
+            ResponseTemplate::new(200)
+                .set_body_json(json!({"jsonrpc": "2.0", "id": 1, "result": result}))
+        };
+        Mock::given(body_partial_json(json!({"method": "getSignatureStatuses"})))
+            .respond_with(respond(json!({"value": [null]})))
+            .up_to_n_times(2)
+            .mount(&node)
+            .await;
+        Mock::given(body_partial_json(json!({"method": "getSignatureStatuses"})))
+            .respond_with(respond(
+                json!({"value": [{"confirmationStatus": "confirmed", "err": null}]}),
+            ))
+            .mount(&node)
+            .await;
+        Mock::given(body_partial_json(json!({"method": "isBlockhashValid"})))
+            .respond_with(respond(json!({"value": true})))
+            .mount(&node)
+            .await;
+        Mock::given(body_partial_json(json!({"method": "sendTransaction"})))
+            .respond_with(respond(json!("signature")))
+            .expect(2)
+            .mount(&node)
+            .await;
+
+        let proxy_provider =
+            Arc::new(ProxyProvider::new(String::new(), false, Duration::from_secs(1)).unwrap());
+        let provider = Arc::new(Provider::from_json(json!({"solana": [node.uri()]})));
+
+        let transaction = bs58::encode(sample_transaction()).into_string();
+        let body = json!({"jsonrpc": "2.0", "id": 1, "method": "sendTransaction", "params": [transaction]});
+        let rebroadcast = Rebroadcast::from_request(
+            Network::SOLANA,
+            provider.clone(),
+            proxy_provider.clone(),
+            body.to_string().as_bytes(),
+            Duration::from_millis(10),
+        )
+        .unwrap();
+        assert_eq!(rebroadcast.signature(), bs58::encode([7; 64]).into_string());
+
+        let mut events = events::subscribe();
+        rebroadcast.run().await;
+        loop {
+            if let Event::Rebroadcast {
+                outcome, resends, ..
+            } = events.recv().await.unwrap().event
+            {
+                assert_eq!(outcome, "confirmed");
+                assert_eq!(resends, 2);
+                break;
+            }
+        }
+
+        // A transaction sent again while it is being rebroadcast isn't rebroadcast twice
+        let rebroadcast = |network| {
+            Rebroadcast::from_request(
+                network,
+                provider.clone(),
+                proxy_provider.clone(),
+                body.to_string().as_bytes(),
+                Duration::from_secs(60),
+            )
+            .unwrap()
+        };
+        assert!(rebroadcast(Network::SOLANA_DEVNET).spawn());
+        assert!(!rebroadcast(Network::SOLANA_DEVNET).spawn());
+
+        let body = json!({"jsonrpc": "2.0", "id": 1, "method": "getSlot"});
+        assert!(Rebroadcast::from_request(
+            Network::SOLANA,
+            provider,
+            proxy_provider,
+            body.to_string().as_bytes(),
+            Duration::from_millis(10),
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn test_concurrent_rebroadcast_limit() {
+        let network = Network::SOLANA;
+        let claims: Vec<Claim> = (0..MAX_CONCURRENT_REBROADCASTS)
+            .map(|i| Claim::new(network, &i.to_string()).unwrap())
+            .collect();
+        assert!(Claim::new(network, "one more").is_none());
+        assert!(Claim::new(Network::SOLANA_DEVNET, "one more").is_some());
+
+        drop(claims);
+        assert!(Claim::new(network, "one more").is_some());
+    }
+}
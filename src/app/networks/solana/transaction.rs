@@ -0,0 +1,353 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use log::debug;
+use serde::Serialize;
+use serde_json::Value;
+
+const COMPUTE_BUDGET_PROGRAM: &str = "ComputeBudget111111111111111111111111111111";
+
+/// Encoding of a serialized transaction in `sendTransaction` parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Base58,
+    Base64,
+}
+
+impl Encoding {
+    /// Reads the `encoding` field of the request config; base58 when absent.
+    pub fn from_params(params: &Value) -> Self {
+        match params[1]["encoding"].as_str() {
+            Some("base64") => Encoding::Base64,
+            _ => Encoding::Base58,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub program_id_index: u8,
+    pub accounts: Vec<u8>,
+    pub data: Vec<u8>,
+}
+
+/// A signed transaction read from its wire format. Accounts loaded through
+/// address lookup tables are not resolved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transaction {
+    pub signatures: Vec<[u8; 64]>,
+    /// `None` for legacy messages.
+    pub version: Option<u8>,
+    pub account_keys: Vec<[u8; 32]>,
+    pub recent_blockhash: [u8; 32],
+    pub instructions: Vec<Instruction>,
+}
+
+/// What the audit log and request events record about a submitted transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TransactionSummary {
+    pub method: String,
+    pub signature: Option<String>,
+    pub fee_payer: Option<String>,
+    pub version: String,
+    pub program_ids: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compute_unit_limit: Option<u32>,
+    /// Priority fee in micro-lamports per compute unit.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compute_unit_price: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub heap_frame_bytes: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub loaded_accounts_data_size_limit: Option<u32>,
+}
+
+impl Transaction {
+    pub fn decode(encoded: &str, encoding: Encoding) -> Result<Self, String> {
+        let bytes = match encoding {
+            Encoding::Base58 => bs58::decode(encoded)
+                .into_vec()
+                .map_err(|e| e.to_string())?,
+            Encoding::Base64 => STANDARD.decode(encoded).map_err(|e| e.to_string())?,
+        };
+        let mut reader = Reader::new(&bytes);
+
+        let signature_count = reader.short_vec_len()?;
+        let signatures = (0..signature_count)
+            .map(|_| reader.array())
+            .collect::<Result<_, _>>()?;
+
+        // Versioned messages set the top bit of the first byte, where legacy
+        // messages keep the signature count of their header
+        let prefix = reader.peek()?;
+        let version = match prefix & 0x80 {
+            0 => None,
+            _ => {
+                reader.byte()?;
+                Some(prefix & 0x7f)
+            }
+        };
+        reader.array::<3>()?;
+
+        let key_count = reader.short_vec_len()?;
+        let account_keys = (0..key_count)
+            .map(|_| reader.array())
+            .collect::<Result<_, _>>()?;
+        let recent_blockhash = reader.array()?;
+
+        let instruction_count = reader.short_vec_len()?;
+        let instructions = (0..instruction_count)
+            .map(|_| {
+                let program_id_index = reader.byte()?;
+                let len = reader.short_vec_len()?;
+                let accounts = reader.bytes(len)?.to_vec();
+                let len = reader.short_vec_len()?;
+                let data = reader.bytes(len)?.to_vec();
+                Ok(Instruction {
+                    program_id_index,
+                    accounts,
+                    data,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+
+        Ok(Transaction {
+            signatures,
+            version,
+            account_keys,
+            recent_blockhash,
+            instructions,
+        })
+    }
+
+    /// Decodes the transactions of `sendTransaction` and `simulateTransaction`
+    /// calls in a request body, single or batched. Undecodable ones are skipped.
+    pub fn inspect(body: &[u8]) -> Vec<TransactionSummary> {
+        let requests = match serde_json::from_slice::<Value>(body) {
+            Ok(Value::Array(requests)) => requests,
+            Ok(request) => vec![request],
+            Err(_) => return Vec::new(),
+        };
+
+        requests
+            .iter()
+            .filter_map(|request| {
+                let method = request["method"].as_str()?;
+                if method != "sendTransaction" && method != "simulateTransaction" {
+                    return None;
+                }
+                let encoded = request["params"][0].as_str()?;
+                let encoding = Encoding::from_params(&request["params"]);
+                match Transaction::decode(encoded, encoding) {
+                    Ok(transaction) => Some(transaction.summary(method)),
+                    Err(e) => {
+                        debug!("Could not decode {} transaction: {}", method, e);
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+
+    pub fn summary(&self, method: &str) -> TransactionSummary {
+        let key = |index: u8| {
+            self.account_keys
+                .get(index as usize)
+                .map(|key| bs58::encode(key).into_string())
+        };
+        let mut summary = TransactionSummary {
+            method: method.to_string(),
+            signature: self.signature(),
+            fee_payer: key(0),
+            version: self
+                .version
+                .map_or("legacy".to_string(), |version| version.to_string()),
+            program_ids: Vec::new(),
+            compute_unit_limit: None,
+            compute_unit_price: None,
+            heap_frame_bytes: None,
+            loaded_accounts_data_size_limit: None,
+        };
+
+        for instruction in &self.instructions {
+            let program_id = key(instruction.program_id_index)
+                .unwrap_or_else(|| format!("#{}", instruction.program_id_index));
+            if program_id == COMPUTE_BUDGET_PROGRAM {
+                let u32_at =
+                    |data: &[u8]| Some(u32::from_le_bytes(data.get(1..5)?.try_into().ok()?));
+                match instruction.data.first() {
+                    Some(1) => summary.heap_frame_bytes = u32_at(&instruction.data),
+                    Some(2) => summary.compute_unit_limit = u32_at(&instruction.data),
+                    Some(3) => {
+                        summary.compute_unit_price = instruction
+                            .data
+                            .get(1..9)
+                            .and_then(|bytes| bytes.try_into().ok())
+                            .map(u64::from_le_bytes)
+                    }
+                    Some(4) => summary.loaded_accounts_data_size_limit = u32_at(&instruction.data),
+                    _ => (),
+                }
+            }
+            if !summary.program_ids.contains(&program_id) {
+                summary.program_ids.push(program_id);
+            }
+        }
+        summary
+    }
+
+    /// The first signature, which identifies the transaction.
+    pub fn signature(&self) -> Option<String> {
+        self.signatures
+            .first()
+            .map(|signature| bs58::encode(signature).into_string())
+    }
+
+    pub fn recent_blockhash(&self) -> String {
+        bs58::encode(self.recent_blockhash).into_string()
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    fn peek(&self) -> Result<u8, String> {
+        self.bytes
+            .get(self.position)
+            .copied()
+            .ok_or_else(|| "Transaction is truncated".to_string())
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        let byte = self.peek()?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .bytes
+            .get(self.position..self.position + len)
+            .ok_or_else(|| "Transaction is truncated".to_string())?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    /// Reads a compact-u16 length prefix.
+    fn short_vec_len(&mut self) -> Result<usize, String> {
+        let mut len = 0;
+        for shift in [0, 7, 14] {
+            let byte = self.byte()?;
+            len |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(len);
+            }
+        }
+        Err("Invalid length prefix".to_string())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A v0 transaction with one signature setting a priority fee and
+    /// transferring from the fee payer.
+    pub(crate) fn sample_transaction() -> Vec<u8> {
+        let mut bytes = vec![1];
+        bytes.extend([7; 64]);
+        bytes.push(0x80);
+        bytes.extend([1, 0, 2]);
+        bytes.push(4);
+        bytes.extend([1; 32]);
+        bytes.extend([2; 32]);
+        bytes.extend([0; 32]);
+        bytes.extend(bs58::decode(COMPUTE_BUDGET_PROGRAM).into_vec().unwrap());
+        bytes.extend([9; 32]);
+        bytes.push(3);
+        // Compute unit limit and price
+        bytes.extend([3, 0, 5, 2]);
+        bytes.extend(200_000u32.to_le_bytes());
+        bytes.extend([3, 0, 9, 3]);
+        bytes.extend(5_000u64.to_le_bytes());
+        // System transfer
+        bytes.extend([2, 2, 0, 1, 12, 2, 0, 0, 0]);
+        bytes.extend(1_000_000u64.to_le_bytes());
+        // No address table lookups
+        bytes.push(0);
+        bytes
+    }
+
+    #[test]
+    fn test_decode_transaction() {
+        let bytes = sample_transaction();
+        let base58 = bs58::encode(&bytes).into_string();
+        let base64 = STANDARD.encode(&bytes);
+
+        let transaction = Transaction::decode(&base58, Encoding::Base58).unwrap();
+        assert_eq!(
+            transaction,
+            Transaction::decode(&base64, Encoding::Base64).unwrap()
+        );
+        assert_eq!(transaction.version, Some(0));
+        assert_eq!(transaction.account_keys.len(), 4);
+        assert_eq!(transaction.instructions.len(), 3);
+        assert_eq!(
+            transaction.signature(),
+            Some(bs58::encode([7; 64]).into_string())
+        );
+        assert_eq!(
+            transaction.recent_blockhash(),
+            bs58::encode([9; 32]).into_string()
+        );
+
+        // Legacy messages start with the header directly
+        let mut legacy = bytes.clone();
+        legacy.remove(65);
+        let transaction =
+            Transaction::decode(&bs58::encode(&legacy).into_string(), Encoding::Base58).unwrap();
+        assert_eq!(transaction.version, None);
+        assert_eq!(transaction.account_keys.len(), 4);
+
+        assert!(Transaction::decode(&base58[..20], Encoding::Base58).is_err());
+    }
+
+    #[test]
+    fn test_inspect_transactions() {
+        let bytes = sample_transaction();
+        let body = serde_json::json!([
+            {"jsonrpc": "2.0", "id": 1, "method": "getSlot"},
+            {"jsonrpc": "2.0", "id": 2, "method": "sendTransaction", "params": [bs58::encode(&bytes).into_string()]},
+            {"jsonrpc": "2.0", "id": 3, "method": "simulateTransaction", "params": [STANDARD.encode(&bytes), {"encoding": "base64"}]},
+            {"jsonrpc": "2.0", "id": 4, "method": "sendTransaction", "params": ["not a transaction"]}
+        ]);
+
+        let summaries = Transaction::inspect(body.to_string().as_bytes());
+        assert_eq!(summaries.len(), 2);
+        assert_eq!(summaries[0].method, "sendTransaction");
+        assert_eq!(summaries[1].method, "simulateTransaction");
+
+        let summary = &summaries[0];
+        assert_eq!(summary.fee_payer, Some(bs58::encode([1; 32]).into_string()));
+        assert_eq!(summary.version, "0");
+        assert_eq!(
+            summary.program_ids,
+            vec![
+                COMPUTE_BUDGET_PROGRAM.to_string(),
+                "11111111111111111111111111111111".to_string()
+            ]
+        );
+        assert_eq!(summary.compute_unit_limit, Some(200_000));
+        assert_eq!(summary.compute_unit_price, Some(5_000));
+        assert_eq!(summary.heap_frame_bytes, None);
+    }
+}
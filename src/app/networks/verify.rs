@@ -0,0 +1,202 @@
+use crate::app::events::{self, Event};
+use crate::app::metrics;
+use crate::app::networks::evm::Evm;
+use crate::app::networks::solana::Solana;
+use crate::provider::proxy::Proxy;
+use crate::provider::{EvmSettings, Network, NetworkKind, ProtocolFamily, Provider, ProxyProvider};
+use crate::utils::redact::redact_url;
+use futures_util::future::join_all;
+use futures_util::{SinkExt, StreamExt};
+use log::{debug, info, warn};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::timeout;
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+const VERIFY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Outcome of checking that a node serves the network it is listed under.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Verified,
+    Mismatch(String),
+    /// The node could not be asked; its current status is kept.
+    Unreachable(String),
+}
+
+/// Calls a single JSON-RPC method on an HTTP or WebSocket node of `network` and returns
+/// its result. HTTP nodes are called through the proxy pool, like client traffic is;
+/// WebSocket nodes are dialed directly, as the subscription hubs do.
+pub async fn call_node(
+    network: Network,
+    proxy_provider: &Arc<ProxyProvider>,
+    url: &str,
+    method: &str,
+    params: Value,
+) -> Result<Value, String> {
+    let request = json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": params});
+    if !(url.starts_with("ws://") || url.starts_with("wss://")) {
+        return timeout(
+            VERIFY_TIMEOUT,
+            Proxy::call(network, proxy_provider.clone(), url, &request),
+        )
+        .await
+        .map_err(|_| "timed out".to_string())?;
+    }
+
+    let response = timeout(VERIFY_TIMEOUT, call_ws_node(url, &request))
+        .await
+        .map_err(|_| "timed out".to_string())??;
+    match response {
+        Value::Object(mut response) => match response.remove("error") {
+            Some(error) => Err(error["message"].as_str().unwrap_or("error").to_string()),
+            None => Ok(response.remove("result").unwrap_or_default()),
+        },
+        _ => Err("invalid JSON-RPC response".to_string()),
+    }
+}
+
+async fn call_ws_node(url: &str, request: &Value) -> Result<Value, String> {
+    let (mut stream, _) = connect_async(url).await.map_err(|e| e.to_string())?;
+    stream
+        .send(Message::Text(request.to_string()))
+        .await
+        .map_err(|e| e.to_string())?;
+
+    while let Some(message) = stream.next().await {
+        if let Message::Text(text) = message.map_err(|e| e.to_string())? {
+            let response: Value = serde_json::from_str(&text).map_err(|e| e.to_string())?;
+            if response["id"] == request["id"] {
+                let _ = stream.close(None).await;
+                return Ok(response);
+            }
+        }
+    }
+    Err("connection closed".to_string())
+}
+
+/// Checks every HTTP and WebSocket node of every registered network, quarantining
+/// the ones that serve a different chain and releasing the ones that match again.
+pub async fn verify_nodes(provider: &Provider, proxy_provider: &Arc<ProxyProvider>) {
+    let checks = Network::all().into_iter().flat_map(|network| {
+        provider
+            .node_urls(network)
+            .into_iter()
+            .chain(provider.program_accounts_node_urls(network))
+            .chain(provider.ws_node_urls(network))
+            .map(move |url| (network, url))
+    });
+
+    let checks: Vec<_> = checks.collect();
+    metrics::reset_node_health();
+    join_all(checks.into_iter().map(|(network, url)| async move {
+        let verdict = match network.kind() {
+            Some(NetworkKind::Evm(EvmSettings {
+                chain_id: Some(chain_id),
+                ..
+            })) => Evm::verify_node(network, proxy_provider, &url, chain_id).await,
+            Some(NetworkKind::Solana(settings)) => {
+                Solana::verify_node(
+                    network,
+                    proxy_provider,
+                    &url,
+                    settings.genesis_hash.as_deref(),
+                    settings.min_version.as_deref(),
+                )
+                .await
+            }
+            _ => Verdict::Verified,
+        };
+        if verdict == Verdict::Verified {
+            if let Some(head) = head(network, proxy_provider, &url).await {
+                provider.record_head(network, &url, head);
+            }
+        }
+        record(provider, network, &url, verdict);
+    }))
+    .await;
+}
+
+/// Re-runs [`verify_nodes`] every `interval`.
+pub async fn verify_periodically(
+    provider: Arc<Provider>,
+    proxy_provider: Arc<ProxyProvider>,
+    interval: Duration,
+) {
+    loop {
+        tokio::time::sleep(interval).await;
+        verify_nodes(&provider, &proxy_provider).await;
+    }
+}
+
+/// Latest block or slot of a node, to tell how far behind the others it is.
+async fn head(network: Network, proxy_provider: &Arc<ProxyProvider>, url: &str) -> Option<u64> {
+    match network.family() {
+        ProtocolFamily::Solana => {
+            let params = json!([{"commitment": "processed"}]);
+            call_node(network, proxy_provider, url, "getSlot", params)
+                .await
+                .ok()?
+                .as_u64()
+        }
+        ProtocolFamily::Evm => {
+            let head = call_node(network, proxy_provider, url, "eth_blockNumber", json!([]))
+                .await
+                .ok()?;
+            u64::from_str_radix(head.as_str()?.strip_prefix("0x")?, 16).ok()
+        }
+        ProtocolFamily::JsonRpc => None,
+    }
+}
+
+fn record(provider: &Provider, network: Network, url: &str, verdict: Verdict) {
+    let outcome = match &verdict {
+        Verdict::Verified => "verified".to_string(),
+        Verdict::Mismatch(reason) => format!("mismatch: {}", reason),
+        Verdict::Unreachable(reason) => format!("unreachable: {}", reason),
+    };
+    provider.record_verification(network, url, outcome);
+
+    match verdict {
+        Verdict::Verified => {
+            metrics::set_node_health(network.as_ref(), url, true);
+            if provider.release(network, url) {
+                info!("Node {} of {} verified again", redact_url(url), network);
+                events::publish(Event::NodeHealth {
+                    network: network.to_string(),
+                    node: redact_url(url),
+                    healthy: true,
+                    reason: "Node identity verified".to_string(),
+                });
+            }
+        }
+        Verdict::Mismatch(reason) => {
+            metrics::set_node_health(network.as_ref(), url, false);
+            if provider.quarantine(network, url, reason.clone()) {
+                warn!(
+                    "Quarantining node {} of {}: {}",
+                    redact_url(url),
+                    network,
+                    reason
+                );
+                events::publish(Event::NodeHealth {
+                    network: network.to_string(),
+                    node: redact_url(url),
+                    healthy: false,
+                    reason,
+                });
+            }
+        }
+        Verdict::Unreachable(reason) => {
+            let healthy = !provider.quarantined(network).contains_key(url);
+            metrics::set_node_health(network.as_ref(), url, healthy);
+            debug!(
+                "Could not verify node {} of {}: {}",
+                redact_url(url),
+                network,
+                reason
+            );
+        }
+    }
+}
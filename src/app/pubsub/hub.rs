@@ -0,0 +1,1418 @@
+use crate::app::events::{self, Event};
+use crate::app::pubsub::protocol::{subscription_key, PubSubProtocol};
+use crate::provider::{Network, Provider};
+use crate::utils::jsonrpc::rpc_error;
+use crate::utils::redact::redact_url;
+use futures_util::stream::SplitSink;
+use futures_util::{SinkExt, StreamExt};
+use log::{debug, error, info, warn};
+use once_cell::sync::Lazy;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{self, Receiver, Sender, UnboundedReceiver, UnboundedSender};
+use tokio::time::timeout;
+use tokio_tungstenite::tungstenite::Message as UpstreamMessage;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+pub type ClientId = u64;
+/// Index of one of the redundant upstream connections of a hub.
+pub type Slot = usize;
+
+const PING_INTERVAL: Duration = Duration::from_secs(15);
+const PONG_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_RECONNECT_ATTEMPTS: usize = 5;
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Longest wait before a slot left without a connection is dialed again.
+const MAX_REDIAL_DELAY: Duration = Duration::from_secs(300);
+/// Rounds of effects applied per hub event; see [`HubTask::apply`].
+const MAX_APPLY_ROUNDS: usize = 2;
+const DEDUP_WINDOW: usize = 4096;
+/// Messages queued for a client before it is considered too slow and disconnected.
+const CLIENT_BUFFER: usize = 1024;
+
+static HUBS: Lazy<Mutex<HashMap<Network, Arc<Hub>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// A side effect produced by [`HubState`] that the hub task has to perform.
+#[derive(Debug, PartialEq)]
+pub enum Effect {
+    Client(ClientId, Value),
+    Upstream(Slot, Value),
+}
+
+#[derive(Debug)]
+enum Command {
+    Register { client: ClientId, tx: Sender<Value> },
+    Request { client: ClientId, request: Value },
+    Disconnect { client: ClientId },
+}
+
+enum UpstreamEvent {
+    Message(String),
+    Alive,
+    Lost,
+    /// Outcome of dialing a slot; `None` once every attempt failed.
+    Connected(Option<Connection>),
+}
+
+#[derive(Debug)]
+enum PendingUpstream {
+    Subscribe(String),
+    Unsubscribe,
+}
+
+#[derive(Debug, Default)]
+struct ClientState {
+    next_subscription_id: u64,
+    subscriptions: HashMap<u64, String>,
+}
+
+/// One subscription shared by every client that subscribed with the same method and
+/// params, held on each upstream slot.
+#[derive(Debug)]
+struct SharedSubscription {
+    method: String,
+    params: Value,
+    upstream_ids: HashMap<Slot, Value>,
+    in_flight: usize,
+    subscribers: Vec<(ClientId, u64)>,
+    waiting: Vec<(ClientId, Value)>,
+    gap: bool,
+}
+
+impl SharedSubscription {
+    fn is_unused(&self) -> bool {
+        self.subscribers.is_empty() && self.waiting.is_empty()
+    }
+}
+
+/// Subscription bookkeeping of a hub: which clients hold which shared subscription and
+/// how client-facing subscription ids map to the upstream ones of every slot.
+pub struct HubState {
+    protocol: PubSubProtocol,
+    slots: usize,
+    deduplicate: bool,
+    next_request_id: u64,
+    clients: HashMap<ClientId, ClientState>,
+    subscriptions: HashMap<String, SharedSubscription>,
+    upstream_index: HashMap<(Slot, String), String>,
+    pending: HashMap<u64, (Slot, PendingUpstream)>,
+    seen: VecDeque<String>,
+    seen_index: HashSet<String>,
+}
+
+impl HubState {
+    pub fn new(protocol: PubSubProtocol, slots: usize, deduplicate: bool) -> Self {
+        Self {
+            protocol,
+            slots: slots.max(1),
+            deduplicate,
+            next_request_id: 1,
+            clients: HashMap::new(),
+            subscriptions: HashMap::new(),
+            upstream_index: HashMap::new(),
+            pending: HashMap::new(),
+            seen: VecDeque::new(),
+            seen_index: HashSet::new(),
+        }
+    }
+
+    pub fn register(&mut self, client: ClientId) {
+        self.clients.insert(client, ClientState::default());
+    }
+
+    /// Applies reloaded settings. Slots dropped by a lower redundancy are forgotten like
+    /// lost ones; new slots take part in subscriptions from now on.
+    pub fn reconfigure(&mut self, slots: usize, deduplicate: bool) {
+        let slots = slots.max(1);
+        for slot in slots..self.slots {
+            self.on_upstream_lost(slot);
+        }
+        self.slots = slots;
+        self.deduplicate = deduplicate;
+    }
+
+    /// True when no upstream subscription is active or being set up.
+    pub fn is_idle(&self) -> bool {
+        self.subscriptions.is_empty() && self.pending.is_empty()
+    }
+
+    pub fn subscription_count(&self) -> usize {
+        self.subscriptions.len()
+    }
+
+    pub fn on_client_request(&mut self, client: ClientId, request: Value) -> Vec<Effect> {
+        let request_id = request.get("id").cloned().unwrap_or(Value::Null);
+        let method = request
+            .get("method")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string();
+        let params = request.get("params").cloned().unwrap_or(json!([]));
+
+        if self.protocol.is_unsubscribe(&method) {
+            let subscription = params
+                .get(0)
+                .and_then(|id| self.protocol.parse_subscription_id(id));
+            let key = subscription.and_then(|id| {
+                self.clients
+                    .get_mut(&client)
+                    .and_then(|state| state.subscriptions.remove(&id))
+                    .map(|key| (id, key))
+            });
+
+            return match key {
+                Some((subscription, key)) => {
+                    let mut effects = self.release(client, subscription, &key);
+                    effects.push(Effect::Client(
+                        client,
+                        json!({ "jsonrpc": "2.0", "id": request_id, "result": true }),
+                    ));
+                    effects
+                }
+                None => vec![Effect::Client(
+                    client,
+                    rpc_error(request_id, -32602, "Invalid subscription id"),
+                )],
+            };
+        }
+
+        let key = format!("{}:{}", method, params);
+        if let Some(shared) = self.subscriptions.get_mut(&key) {
+            if !shared.upstream_ids.is_empty() {
+                let effect = Self::confirm(
+                    self.protocol,
+                    &mut self.clients,
+                    &key,
+                    shared,
+                    client,
+                    request_id,
+                );
+                return effect.into_iter().collect();
+            }
+            shared.waiting.push((client, request_id));
+            return Vec::new();
+        }
+
+        self.subscriptions.insert(
+            key.clone(),
+            SharedSubscription {
+                method,
+                params,
+                upstream_ids: HashMap::new(),
+                in_flight: 0,
+                subscribers: Vec::new(),
+                waiting: vec![(client, request_id)],
+                gap: false,
+            },
+        );
+        (0..self.slots)
+            .filter_map(|slot| self.subscribe_upstream(slot, &key))
+            .collect()
+    }
+
+    pub fn on_upstream_message(&mut self, slot: Slot, text: &str) -> Vec<Effect> {
+        let message: Value = match serde_json::from_str(text) {
+            Ok(message) => message,
+            Err(e) => {
+                warn!("Invalid upstream message: {}", e);
+                return Vec::new();
+            }
+        };
+
+        if let Some(request_id) = message.get("id").and_then(Value::as_u64) {
+            return match self.pending.remove(&request_id) {
+                Some((slot, PendingUpstream::Subscribe(key))) => {
+                    self.on_subscribed(slot, key, message)
+                }
+                Some((_, PendingUpstream::Unsubscribe)) => Vec::new(),
+                None => {
+                    debug!("Unexpected upstream response id: {}", request_id);
+                    Vec::new()
+                }
+            };
+        }
+
+        let method = message
+            .get("method")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        let Some(upstream_id) = message
+            .pointer("/params/subscription")
+            .map(subscription_key)
+        else {
+            debug!("Ignoring upstream message without subscription: {}", text);
+            return Vec::new();
+        };
+        let Some(key) = self.upstream_index.get(&(slot, upstream_id)).cloned() else {
+            debug!("Notification for unknown subscription on slot {}", slot);
+            return Vec::new();
+        };
+
+        // Redundant upstreams deliver the same notification once each
+        if self.deduplicate && self.slots > 1 {
+            let result = message.pointer("/params/result").unwrap_or(&Value::Null);
+            let identity = format!("{}|{}", key, self.protocol.dedup_key(result));
+            if !self.remember(identity) {
+                return Vec::new();
+            }
+        }
+
+        let one_shot = self.protocol.is_one_shot_notification(method);
+        let Some(shared) = self.subscriptions.get(&key) else {
+            return Vec::new();
+        };
+        let effects = shared
+            .subscribers
+            .iter()
+            .map(|&(client, subscription)| {
+                let mut notification = message.clone();
+                notification["params"]["subscription"] =
+                    self.protocol.format_subscription_id(subscription);
+                Effect::Client(client, notification)
+            })
+            .collect();
+
+        if one_shot {
+            if let Some(shared) = self.subscriptions.remove(&key) {
+                for (slot, upstream_id) in shared.upstream_ids {
+                    self.upstream_index
+                        .remove(&(slot, subscription_key(&upstream_id)));
+                }
+                for (client, subscription) in shared.subscribers {
+                    if let Some(state) = self.clients.get_mut(&client) {
+                        state.subscriptions.remove(&subscription);
+                    }
+                }
+            }
+        }
+
+        effects
+    }
+
+    /// Drops every subscription of a disconnected client.
+    pub fn disconnect(&mut self, client: ClientId) -> Vec<Effect> {
+        let Some(state) = self.clients.remove(&client) else {
+            return Vec::new();
+        };
+
+        for shared in self.subscriptions.values_mut() {
+            shared.waiting.retain(|(waiting, _)| *waiting != client);
+        }
+
+        state
+            .subscriptions
+            .into_iter()
+            .flat_map(|(subscription, key)| self.release(client, subscription, &key))
+            .collect()
+    }
+
+    /// Forgets the upstream subscription ids of a lost slot, keeping the client side intact
+    /// so [`HubState::resubscribe`] can restore it.
+    pub fn on_upstream_lost(&mut self, slot: Slot) {
+        let lost: Vec<u64> = self
+            .pending
+            .iter()
+            .filter(|(_, (pending_slot, _))| *pending_slot == slot)
+            .map(|(request_id, _)| *request_id)
+            .collect();
+        for request_id in lost {
+            if let Some((_, PendingUpstream::Subscribe(key))) = self.pending.remove(&request_id) {
+                if let Some(shared) = self.subscriptions.get_mut(&key) {
+                    shared.in_flight -= 1;
+                }
+            }
+        }
+
+        self.upstream_index
+            .retain(|(indexed_slot, _), _| *indexed_slot != slot);
+        for shared in self.subscriptions.values_mut() {
+            shared.upstream_ids.remove(&slot);
+            if shared.upstream_ids.is_empty() && !shared.subscribers.is_empty() {
+                shared.gap = true;
+            }
+        }
+        self.subscriptions.retain(|_, shared| {
+            !(shared.is_unused() && shared.upstream_ids.is_empty() && shared.in_flight == 0)
+        });
+    }
+
+    /// Subscribes again to every known subscription on a fresh connection of `slot`.
+    pub fn resubscribe(&mut self, slot: Slot) -> Vec<Effect> {
+        let keys: Vec<String> = self.subscriptions.keys().cloned().collect();
+        keys.into_iter()
+            .filter_map(|key| self.subscribe_upstream(slot, &key))
+            .collect()
+    }
+
+    /// Ends subscriptions that are neither held nor being set up on any slot; used when
+    /// a slot cannot be reconnected.
+    pub fn abandon_orphans(&mut self) -> Vec<Effect> {
+        let orphans: Vec<String> = self
+            .subscriptions
+            .iter()
+            .filter(|(_, shared)| shared.upstream_ids.is_empty() && shared.in_flight == 0)
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        let mut effects = Vec::new();
+        for key in orphans {
+            let shared = self.subscriptions.remove(&key).unwrap();
+            effects.extend(shared.waiting.into_iter().map(|(client, request_id)| {
+                Effect::Client(
+                    client,
+                    rpc_error(request_id, -32603, "Upstream unavailable"),
+                )
+            }));
+            effects.extend(self.end_subscribers(shared.subscribers));
+        }
+        effects
+    }
+
+    fn on_subscribed(&mut self, slot: Slot, key: String, response: Value) -> Vec<Effect> {
+        let upstream_id = response.get("result").cloned();
+        let Some(shared) = self.subscriptions.get_mut(&key) else {
+            let method = key
+                .split_once(':')
+                .map_or(key.as_str(), |(method, _)| method);
+            return match upstream_id {
+                Some(upstream_id) => vec![self.unsubscribe_upstream(slot, method, upstream_id)],
+                None => Vec::new(),
+            };
+        };
+        shared.in_flight -= 1;
+
+        let Some(upstream_id) = upstream_id else {
+            // Another slot may still hold or set up the subscription
+            if !shared.upstream_ids.is_empty() || shared.in_flight > 0 {
+                return Vec::new();
+            }
+            let shared = self.subscriptions.remove(&key).unwrap();
+            let mut effects: Vec<Effect> = shared
+                .waiting
+                .into_iter()
+                .map(|(client, request_id)| {
+                    let mut response = response.clone();
+                    response["id"] = request_id;
+                    Effect::Client(client, response)
+                })
+                .collect();
+            // A failed resubscription ends the subscription for clients that already held it
+            effects.extend(self.end_subscribers(shared.subscribers));
+            return effects;
+        };
+
+        // Everyone left while the subscription was being set up
+        if shared.is_unused() {
+            let method = shared.method.clone();
+            if shared.in_flight == 0 && shared.upstream_ids.is_empty() {
+                self.subscriptions.remove(&key);
+            }
+            return vec![self.unsubscribe_upstream(slot, &method, upstream_id)];
+        }
+
+        shared.upstream_ids.insert(slot, upstream_id.clone());
+        self.upstream_index
+            .insert((slot, subscription_key(&upstream_id)), key.clone());
+
+        // Existing subscribers only see this after a reconnect: they may have missed data
+        let mut effects = Vec::new();
+        if std::mem::take(&mut shared.gap) {
+            effects.extend(shared.subscribers.iter().map(|&(client, subscription)| {
+                Self::gap_notification(self.protocol, client, subscription, true)
+            }));
+        }
+        for (client, request_id) in std::mem::take(&mut shared.waiting) {
+            effects.extend(Self::confirm(
+                self.protocol,
+                &mut self.clients,
+                &key,
+                shared,
+                client,
+                request_id,
+            ));
+        }
+        effects
+    }
+
+    fn confirm(
+        protocol: PubSubProtocol,
+        clients: &mut HashMap<ClientId, ClientState>,
+        key: &str,
+        shared: &mut SharedSubscription,
+        client: ClientId,
+        request_id: Value,
+    ) -> Option<Effect> {
+        let state = clients.get_mut(&client)?;
+        state.next_subscription_id += 1;
+        let subscription = state.next_subscription_id;
+        state.subscriptions.insert(subscription, key.to_string());
+        shared.subscribers.push((client, subscription));
+        Some(Effect::Client(
+            client,
+            json!({
+                "jsonrpc": "2.0",
+                "id": request_id,
+                "result": protocol.format_subscription_id(subscription),
+            }),
+        ))
+    }
+
+    fn end_subscribers(&mut self, subscribers: Vec<(ClientId, u64)>) -> Vec<Effect> {
+        subscribers
+            .into_iter()
+            .map(|(client, subscription)| {
+                if let Some(state) = self.clients.get_mut(&client) {
+                    state.subscriptions.remove(&subscription);
+                }
+                Self::gap_notification(self.protocol, client, subscription, false)
+            })
+            .collect()
+    }
+
+    /// Tells a client that notifications of `subscription` may have been missed while the
+    /// upstream was reconnecting, and whether the subscription is still alive.
+    fn gap_notification(
+        protocol: PubSubProtocol,
+        client: ClientId,
+        subscription: u64,
+        resubscribed: bool,
+    ) -> Effect {
+        Effect::Client(
+            client,
+            json!({
+                "jsonrpc": "2.0",
+                "method": "subscriptionGap",
+                "params": {
+                    "subscription": protocol.format_subscription_id(subscription),
+                    "resubscribed": resubscribed,
+                },
+            }),
+        )
+    }
+
+    fn release(&mut self, client: ClientId, subscription: u64, key: &str) -> Vec<Effect> {
+        let Some(shared) = self.subscriptions.get_mut(key) else {
+            return Vec::new();
+        };
+        shared
+            .subscribers
+            .retain(|&entry| entry != (client, subscription));
+        if !shared.is_unused() {
+            return Vec::new();
+        }
+
+        let method = shared.method.clone();
+        let upstream_ids: Vec<(Slot, Value)> = shared.upstream_ids.drain().collect();
+        // Subscriptions still being set up are cleaned up by `on_subscribed`
+        if shared.in_flight == 0 {
+            self.subscriptions.remove(key);
+        }
+        upstream_ids
+            .into_iter()
+            .map(|(slot, upstream_id)| {
+                self.upstream_index
+                    .remove(&(slot, subscription_key(&upstream_id)));
+                self.unsubscribe_upstream(slot, &method, upstream_id)
+            })
+            .collect()
+    }
+
+    fn subscribe_upstream(&mut self, slot: Slot, key: &str) -> Option<Effect> {
+        let shared = self.subscriptions.get_mut(key)?;
+        shared.in_flight += 1;
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": self.next_request_id,
+            "method": shared.method,
+            "params": shared.params,
+        });
+        self.pending.insert(
+            self.next_request_id,
+            (slot, PendingUpstream::Subscribe(key.to_string())),
+        );
+        self.next_request_id += 1;
+        Some(Effect::Upstream(slot, request))
+    }
+
+    fn unsubscribe_upstream(&mut self, slot: Slot, method: &str, upstream_id: Value) -> Effect {
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": self.next_request_id,
+            "method": self.protocol.unsubscribe_method(method),
+            "params": [upstream_id],
+        });
+        self.pending
+            .insert(self.next_request_id, (slot, PendingUpstream::Unsubscribe));
+        self.next_request_id += 1;
+        Effect::Upstream(slot, request)
+    }
+
+    /// Records a notification identity; false if it was already seen recently.
+    fn remember(&mut self, identity: String) -> bool {
+        if !self.seen_index.insert(identity.clone()) {
+            return false;
+        }
+        self.seen.push_back(identity);
+        if self.seen.len() > DEDUP_WINDOW {
+            if let Some(oldest) = self.seen.pop_front() {
+                self.seen_index.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+type UpstreamSink = SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, UpstreamMessage>;
+
+struct Connection {
+    tx: UpstreamSink,
+    url: String,
+    generation: u64,
+    last_seen: Instant,
+}
+
+/// Upstream connection of one slot and its reconnection progress.
+#[derive(Default)]
+struct SlotState {
+    connection: Option<Connection>,
+    /// Generation of the connection being dialed in the background.
+    dialing: Option<u64>,
+    /// Connections lost in a row without the upstream sending anything.
+    failures: usize,
+    /// Times in a row the slot was given up on, spacing out its redials.
+    redials: u32,
+    /// When the slot, left without a connection, is dialed again.
+    redial_at: Option<Instant>,
+}
+
+impl SlotState {
+    /// Leaves the slot without a connection until the next redial, backing off
+    /// further each time.
+    fn give_up(&mut self) {
+        let delay = PING_INTERVAL.saturating_mul(1 << self.redials.min(5));
+        self.redial_at = Some(Instant::now() + delay.min(MAX_REDIAL_DELAY));
+        self.redials += 1;
+    }
+}
+
+/// Multiplexes the subscriptions of every client of one network over one upstream
+/// WebSocket connection per slot.
+pub struct Hub {
+    commands: UnboundedSender<Command>,
+    next_client_id: AtomicU64,
+}
+
+impl Hub {
+    /// Returns the hub of `network`, starting it on first use.
+    pub fn get(network: Network, protocol: PubSubProtocol, provider: Arc<Provider>) -> Arc<Hub> {
+        let mut hubs = HUBS.lock().unwrap();
+        if let Some(hub) = hubs.get(&network) {
+            if !hub.commands.is_closed() {
+                return hub.clone();
+            }
+        }
+
+        let settings = provider.ws_settings(network);
+        let state = HubState::new(protocol, settings.redundancy, settings.deduplicate);
+        let (commands, commands_rx) = mpsc::unbounded_channel();
+        let hub = Arc::new(Hub {
+            commands,
+            next_client_id: AtomicU64::new(1),
+        });
+        tokio::spawn(HubTask::new(network, provider, state).run(commands_rx));
+        hubs.insert(network, hub.clone());
+        hub
+    }
+
+    /// Registers a client; the receiver is closed once the client falls too
+    /// far behind on its messages.
+    pub fn register(&self) -> (ClientId, Receiver<Value>) {
+        let client = self.next_client_id.fetch_add(1, Ordering::SeqCst);
+        let (tx, rx) = mpsc::channel(CLIENT_BUFFER);
+        let _ = self.commands.send(Command::Register { client, tx });
+        (client, rx)
+    }
+
+    pub fn request(&self, client: ClientId, request: Value) {
+        let _ = self.commands.send(Command::Request { client, request });
+    }
+
+    pub fn disconnect(&self, client: ClientId) {
+        let _ = self.commands.send(Command::Disconnect { client });
+    }
+}
+
+/// The task behind a [`Hub`]: owns the upstream connections and applies the effects of
+/// [`HubState`]. Connections are dialed by separate tasks so a slow or unreachable node
+/// never holds up the clients or the other slots.
+struct HubTask {
+    network: Network,
+    provider: Arc<Provider>,
+    state: HubState,
+    clients: HashMap<ClientId, Sender<Value>>,
+    slots: Vec<SlotState>,
+    events_tx: UnboundedSender<(Slot, u64, UpstreamEvent)>,
+    events_rx: UnboundedReceiver<(Slot, u64, UpstreamEvent)>,
+    next_generation: u64,
+}
+
+impl HubTask {
+    fn new(network: Network, provider: Arc<Provider>, state: HubState) -> Self {
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+        let slots = (0..state.slots).map(|_| SlotState::default()).collect();
+        Self {
+            network,
+            provider,
+            state,
+            clients: HashMap::new(),
+            slots,
+            events_tx,
+            events_rx,
+            next_generation: 1,
+        }
+    }
+
+    async fn run(mut self, mut commands: UnboundedReceiver<Command>) {
+        let mut heartbeat = tokio::time::interval(PING_INTERVAL);
+
+        loop {
+            let effects = tokio::select! {
+                command = commands.recv() => match command {
+                    Some(Command::Register { client, tx }) => {
+                        self.state.register(client);
+                        self.clients.insert(client, tx);
+                        continue;
+                    }
+                    Some(Command::Request { client, request }) => {
+                        self.state.on_client_request(client, request)
+                    }
+                    Some(Command::Disconnect { client }) => {
+                        self.clients.remove(&client);
+                        self.state.disconnect(client)
+                    }
+                    None => break,
+                },
+                Some((slot, generation, event)) = self.events_rx.recv() => {
+                    if slot >= self.slots.len() {
+                        // The slot was dropped by a reload while it was being dialed
+                        if let UpstreamEvent::Connected(Some(mut connection)) = event {
+                            let _ = connection.tx.send(UpstreamMessage::Close(None)).await;
+                        }
+                        continue;
+                    }
+                    if let UpstreamEvent::Connected(connection) = event {
+                        self.on_connected(slot, generation, connection).await
+                    } else {
+                        let state = &mut self.slots[slot];
+                        let Some(connection) = state
+                            .connection
+                            .as_mut()
+                            .filter(|connection| connection.generation == generation)
+                        else {
+                            continue;
+                        };
+                        connection.last_seen = Instant::now();
+                        match event {
+                            UpstreamEvent::Message(text) => {
+                                state.failures = 0;
+                                self.state.on_upstream_message(slot, &text)
+                            }
+                            UpstreamEvent::Alive => {
+                                state.failures = 0;
+                                continue;
+                            }
+                            UpstreamEvent::Lost => self.recover(slot),
+                            UpstreamEvent::Connected(_) => unreachable!(),
+                        }
+                    }
+                },
+                _ = heartbeat.tick() => self.heartbeat().await,
+            };
+
+            self.apply(effects).await;
+
+            if self.state.is_idle() {
+                for state in self.slots.iter_mut() {
+                    state.dialing = None;
+                    state.failures = 0;
+                    state.redials = 0;
+                    state.redial_at = None;
+                    if let Some(mut connection) = state.connection.take() {
+                        debug!("No active {} subscriptions, closing upstream", self.network);
+                        let _ = connection.tx.send(UpstreamMessage::Close(None)).await;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn apply(&mut self, mut effects: Vec<Effect>) {
+        // Recovering a slot only yields client effects, so a second round is the last
+        for _ in 0..MAX_APPLY_ROUNDS {
+            if effects.is_empty() {
+                return;
+            }
+            let mut failed = Vec::new();
+            for effect in std::mem::take(&mut effects) {
+                match effect {
+                    Effect::Client(client, message) => {
+                        let Some(tx) = self.clients.get(&client) else {
+                            continue;
+                        };
+                        if let Err(TrySendError::Full(_)) = tx.try_send(message) {
+                            // Dropping the sender ends the client's session, which
+                            // then disconnects it from the hub
+                            warn!(
+                                "{} WebSocket client {} is not keeping up, disconnecting",
+                                self.network, client
+                            );
+                            self.clients.remove(&client);
+                        }
+                    }
+                    Effect::Upstream(slot, _) if failed.contains(&slot) => {}
+                    Effect::Upstream(slot, message) => {
+                        let sent = match self.slots[slot].connection.as_mut() {
+                            Some(connection) => connection
+                                .tx
+                                .send(UpstreamMessage::Text(message.to_string()))
+                                .await
+                                .is_ok(),
+                            None => false,
+                        };
+                        if !sent {
+                            failed.push(slot);
+                        }
+                    }
+                }
+            }
+            // Everything not yet sent on a failed slot is restored by resubscribing
+            // once it is connected
+            for slot in failed {
+                effects.extend(self.recover(slot));
+            }
+        }
+        if !effects.is_empty() {
+            warn!(
+                "Dropping {} effects of the {} hub",
+                effects.len(),
+                self.network
+            );
+        }
+    }
+
+    async fn heartbeat(&mut self) -> Vec<Effect> {
+        let mut effects = self.reconfigure().await;
+        for slot in 0..self.slots.len() {
+            let state = &self.slots[slot];
+            if state.connection.is_none() && state.dialing.is_none() && !self.state.is_idle() {
+                if state.redial_at.is_none_or(|at| at <= Instant::now()) {
+                    info!("Redialing {} upstream WebSocket", self.network);
+                    effects.extend(self.recover(slot));
+                }
+                continue;
+            }
+            let Some(connection) = self.slots[slot].connection.as_mut() else {
+                continue;
+            };
+            if connection.last_seen.elapsed() > PING_INTERVAL + PONG_TIMEOUT {
+                warn!("{} upstream WebSocket timed out", self.network);
+                effects.extend(self.recover(slot));
+            } else if connection
+                .tx
+                .send(UpstreamMessage::Ping(Vec::new()))
+                .await
+                .is_err()
+            {
+                effects.extend(self.recover(slot));
+            }
+        }
+        effects
+    }
+
+    /// Picks up WebSocket settings changed by a reload of the node lists, closing the
+    /// connections of dropped slots and connecting added ones.
+    async fn reconfigure(&mut self) -> Vec<Effect> {
+        let settings = self.provider.ws_settings(self.network);
+        let slots = settings.redundancy.max(1);
+        self.state.reconfigure(slots, settings.deduplicate);
+        if slots == self.slots.len() {
+            return Vec::new();
+        }
+
+        info!(
+            "Holding {} subscriptions on {} upstream connections instead of {}",
+            self.network,
+            slots,
+            self.slots.len()
+        );
+        while self.slots.len() > slots {
+            if let Some(mut connection) = self.slots.pop().and_then(|state| state.connection) {
+                let _ = connection.tx.send(UpstreamMessage::Close(None)).await;
+            }
+        }
+        let mut effects = Vec::new();
+        while self.slots.len() < slots {
+            self.slots.push(SlotState::default());
+            effects.extend(self.recover(self.slots.len() - 1));
+        }
+        effects
+    }
+
+    /// Drops the connection of `slot`, if any, and connects it again in the background,
+    /// preferring another node. Every active subscription is re-established once the
+    /// new connection is up; client-facing subscription ids do not change.
+    fn recover(&mut self, slot: Slot) -> Vec<Effect> {
+        let failed_url = self.slots[slot]
+            .connection
+            .take()
+            .map(|connection| connection.url);
+        if let Some(url) = &failed_url {
+            events::publish(Event::NodeHealth {
+                network: self.network.to_string(),
+                node: redact_url(url),
+                healthy: false,
+                reason: "WebSocket connection lost".to_string(),
+            });
+            self.slots[slot].failures += 1;
+        }
+        self.state.on_upstream_lost(slot);
+        if self.state.is_idle() {
+            return Vec::new();
+        }
+
+        // Connections that keep failing right after being established are given up on
+        if self.slots[slot].failures > MAX_RECONNECT_ATTEMPTS {
+            error!(
+                "Could not keep {} upstream WebSocket connected",
+                self.network
+            );
+            self.slots[slot].failures = 0;
+            self.slots[slot].give_up();
+            return self.state.abandon_orphans();
+        }
+        if self.slots[slot].dialing.is_none() {
+            let avoid = match failed_url {
+                Some(url) => vec![url],
+                // Prefer a node no other slot is connected to
+                None => self
+                    .slots
+                    .iter()
+                    .filter_map(|state| state.connection.as_ref())
+                    .map(|connection| connection.url.clone())
+                    .collect(),
+            };
+            let generation = self.next_generation;
+            self.next_generation += 1;
+            self.slots[slot].dialing = Some(generation);
+            tokio::spawn(dial(
+                self.network,
+                self.provider.clone(),
+                slot,
+                generation,
+                avoid,
+                self.events_tx.clone(),
+            ));
+        }
+        Vec::new()
+    }
+
+    async fn on_connected(
+        &mut self,
+        slot: Slot,
+        generation: u64,
+        connection: Option<Connection>,
+    ) -> Vec<Effect> {
+        let state = &mut self.slots[slot];
+        if state.dialing != Some(generation) {
+            if let Some(mut connection) = connection {
+                let _ = connection.tx.send(UpstreamMessage::Close(None)).await;
+            }
+            return Vec::new();
+        }
+        state.dialing = None;
+
+        let Some(connection) = connection else {
+            error!("Could not connect {} upstream WebSocket", self.network);
+            state.give_up();
+            self.state.on_upstream_lost(slot);
+            return self.state.abandon_orphans();
+        };
+        if state.failures > 0 {
+            events::publish(Event::NodeHealth {
+                network: self.network.to_string(),
+                node: redact_url(&connection.url),
+                healthy: true,
+                reason: "WebSocket reconnected".to_string(),
+            });
+        }
+        state.connection = Some(connection);
+        state.redials = 0;
+        state.redial_at = None;
+        info!(
+            "Connected {} upstream, subscribing {} subscriptions",
+            self.network,
+            self.state.subscription_count()
+        );
+        self.state.resubscribe(slot)
+    }
+}
+
+/// Connects `slot` to a node, retrying a few times, and reports the outcome to the hub
+/// task as [`UpstreamEvent::Connected`].
+async fn dial(
+    network: Network,
+    provider: Arc<Provider>,
+    slot: Slot,
+    generation: u64,
+    avoid: Vec<String>,
+    events: UnboundedSender<(Slot, u64, UpstreamEvent)>,
+) {
+    for attempt in 1..=MAX_RECONNECT_ATTEMPTS {
+        if attempt > 1 {
+            tokio::time::sleep(RECONNECT_DELAY).await;
+        }
+        if events.is_closed() {
+            return;
+        }
+        if let Some(connection) =
+            connect_avoiding(network, &provider, slot, generation, &avoid, &events).await
+        {
+            let _ = events.send((slot, generation, UpstreamEvent::Connected(Some(connection))));
+            return;
+        }
+        warn!(
+            "Connecting {} upstream failed. Attempt: {}",
+            network, attempt
+        );
+    }
+    let _ = events.send((slot, generation, UpstreamEvent::Connected(None)));
+}
+
+async fn connect_avoiding(
+    network: Network,
+    provider: &Provider,
+    slot: Slot,
+    generation: u64,
+    avoid: &[String],
+    events: &UnboundedSender<(Slot, u64, UpstreamEvent)>,
+) -> Option<Connection> {
+    let candidates = provider.ws_node_count(network);
+    let mut ws_url = None;
+    for _ in 0..candidates {
+        let url = provider.get_ws_node_url(network).await?;
+        let avoided = avoid.contains(&url);
+        if ws_url.is_none() || !avoided {
+            ws_url = Some(url);
+        }
+        if !avoided {
+            break;
+        }
+    }
+    let Some(ws_url) = ws_url else {
+        error!("Error getting WebSocket node URL. Network: {}", network);
+        return None;
+    };
+    debug!("WebSocket URL: {}", ws_url);
+
+    let connected = match timeout(CONNECT_TIMEOUT, connect_async(ws_url.as_str())).await {
+        Ok(Ok((stream, _))) => Ok(stream),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err("timed out".to_string()),
+    };
+    let stream = match connected {
+        Ok(stream) => stream,
+        Err(e) => {
+            error!("Error connecting to WebSocket node: {}", e);
+            events::publish(Event::NodeHealth {
+                network: network.to_string(),
+                node: redact_url(&ws_url),
+                healthy: false,
+                reason: format!("WebSocket connect failed: {}", e),
+            });
+            return None;
+        }
+    };
+    info!("Connected {} upstream WebSocket slot {}", network, slot);
+
+    let (tx, mut rx) = stream.split();
+    let events = events.clone();
+    tokio::spawn(async move {
+        loop {
+            let event = match rx.next().await {
+                Some(Ok(UpstreamMessage::Text(text))) => UpstreamEvent::Message(text),
+                Some(Ok(UpstreamMessage::Close(frame))) => {
+                    warn!("Upstream WebSocket closed: {:?}", frame);
+                    UpstreamEvent::Lost
+                }
+                Some(Ok(_)) => UpstreamEvent::Alive,
+                Some(Err(e)) => {
+                    error!("Upstream WebSocket error: {:?}", e);
+                    UpstreamEvent::Lost
+                }
+                None => UpstreamEvent::Lost,
+            };
+            let lost = matches!(event, UpstreamEvent::Lost);
+            if events.send((slot, generation, event)).is_err() || lost {
+                break;
+            }
+        }
+    });
+
+    Some(Connection {
+        tx,
+        url: ws_url,
+        generation,
+        last_seen: Instant::now(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upstream_request(effects: Vec<Effect>) -> Value {
+        match effects.as_slice() {
+            [Effect::Upstream(_, value)] => value.clone(),
+            other => panic!("Expected a single upstream request, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_shared_subscription_fan_out() {
+        let mut state = HubState::new(PubSubProtocol::Solana, 1, false);
+        state.register(1);
+        state.register(2);
+
+        let subscribe = json!({"jsonrpc":"2.0","id":10,"method":"logsSubscribe","params":[{"mentions":["Prog"]}]});
+        let request = upstream_request(state.on_client_request(1, subscribe.clone()));
+        assert!(state.on_client_request(2, subscribe).is_empty());
+
+        let effects = state.on_upstream_message(
+            0,
+            &json!({"jsonrpc":"2.0","id":request["id"],"result":77}).to_string(),
+        );
+        assert_eq!(
+            effects,
+            vec![
+                Effect::Client(1, json!({"jsonrpc":"2.0","id":10,"result":1})),
+                Effect::Client(2, json!({"jsonrpc":"2.0","id":10,"result":1})),
+            ]
+        );
+
+        // A third subscriber is answered without another upstream subscription
+        state.register(3);
+        let effects = state.on_client_request(
+            3,
+            json!({"jsonrpc":"2.0","id":"x","method":"logsSubscribe","params":[{"mentions":["Prog"]}]}),
+        );
+        assert_eq!(
+            effects,
+            vec![Effect::Client(
+                3,
+                json!({"jsonrpc":"2.0","id":"x","result":1})
+            )]
+        );
+        assert_eq!(state.subscription_count(), 1);
+
+        let effects = state.on_upstream_message(
+            0,
+            r#"{"jsonrpc":"2.0","method":"logsNotification","params":{"result":{},"subscription":77}}"#,
+        );
+        assert_eq!(effects.len(), 3);
+
+        // Only the last client leaving unsubscribes upstream
+        let effects = state.on_client_request(
+            1,
+            json!({"jsonrpc":"2.0","id":11,"method":"logsUnsubscribe","params":[1]}),
+        );
+        assert_eq!(
+            effects,
+            vec![Effect::Client(
+                1,
+                json!({"jsonrpc":"2.0","id":11,"result":true})
+            )]
+        );
+        assert!(state.disconnect(2).is_empty());
+
+        let request = upstream_request(state.disconnect(3));
+        assert_eq!(request["method"], "logsUnsubscribe");
+        assert_eq!(request["params"], json!([77]));
+        assert_eq!(state.subscription_count(), 0);
+    }
+
+    #[test]
+    fn test_subscriber_leaves_before_confirmation() {
+        let mut state = HubState::new(PubSubProtocol::Solana, 1, false);
+        state.register(1);
+
+        let request = upstream_request(
+            state.on_client_request(1, json!({"jsonrpc":"2.0","id":1,"method":"slotSubscribe"})),
+        );
+        assert!(state.disconnect(1).is_empty());
+
+        let unsubscribe = upstream_request(state.on_upstream_message(
+            0,
+            &json!({"jsonrpc":"2.0","id":request["id"],"result":5}).to_string(),
+        ));
+        assert_eq!(unsubscribe["method"], "slotUnsubscribe");
+        assert_eq!(unsubscribe["params"], json!([5]));
+        assert_eq!(state.subscription_count(), 0);
+    }
+
+    #[test]
+    fn test_resubscribe_after_upstream_lost() {
+        let mut state = HubState::new(PubSubProtocol::Solana, 1, false);
+        state.register(1);
+
+        let request = upstream_request(state.on_client_request(
+            1,
+            json!({"jsonrpc":"2.0","id":1,"method":"accountSubscribe","params":["Acc"]}),
+        ));
+        state.on_upstream_message(
+            0,
+            &json!({"jsonrpc":"2.0","id":request["id"],"result":5}).to_string(),
+        );
+
+        state.on_upstream_lost(0);
+        let request = upstream_request(state.resubscribe(0));
+        assert_eq!(request["method"], "accountSubscribe");
+        assert_eq!(request["params"], json!(["Acc"]));
+
+        let effects = state.on_upstream_message(
+            0,
+            &json!({"jsonrpc":"2.0","id":request["id"],"result":9}).to_string(),
+        );
+        assert_eq!(
+            effects,
+            vec![Effect::Client(
+                1,
+                json!({"jsonrpc":"2.0","method":"subscriptionGap","params":{"subscription":1,"resubscribed":true}})
+            )]
+        );
+
+        // Notifications from the new upstream subscription keep the client-facing id
+        let effects = state.on_upstream_message(
+            0,
+            r#"{"jsonrpc":"2.0","method":"accountNotification","params":{"result":{},"subscription":9}}"#,
+        );
+        match effects.as_slice() {
+            [Effect::Client(1, notification)] => {
+                assert_eq!(notification["params"]["subscription"], 1)
+            }
+            other => panic!("Unexpected effects: {:?}", other),
+        }
+        assert!(state
+            .on_upstream_message(
+                0,
+                r#"{"jsonrpc":"2.0","method":"accountNotification","params":{"result":{},"subscription":5}}"#,
+            )
+            .is_empty());
+    }
+
+    #[test]
+    fn test_redundant_eth_subscription_deduplication() {
+        let mut state = HubState::new(PubSubProtocol::Ethereum, 2, true);
+        state.register(1);
+
+        let effects = state.on_client_request(
+            1,
+            json!({"jsonrpc":"2.0","id":1,"method":"eth_subscribe","params":["newHeads"]}),
+        );
+        assert_eq!(effects.len(), 2);
+        let requests: Vec<(Slot, Value)> = effects
+            .into_iter()
+            .map(|effect| match effect {
+                Effect::Upstream(slot, request) => (slot, request),
+                other => panic!("Unexpected effect: {:?}", other),
+            })
+            .collect();
+
+        let effects = state.on_upstream_message(
+            requests[0].0,
+            &json!({"jsonrpc":"2.0","id":requests[0].1["id"],"result":"0xaa"}).to_string(),
+        );
+        assert_eq!(
+            effects,
+            vec![Effect::Client(
+                1,
+                json!({"jsonrpc":"2.0","id":1,"result":"0x1"})
+            )]
+        );
+        assert!(state
+            .on_upstream_message(
+                requests[1].0,
+                &json!({"jsonrpc":"2.0","id":requests[1].1["id"],"result":"0xbb"}).to_string(),
+            )
+            .is_empty());
+
+        let head = |slot: Slot, subscription: &str| {
+            let text = json!({
+                "jsonrpc": "2.0",
+                "method": "eth_subscription",
+                "params": {"subscription": subscription, "result": {"hash": "0x01", "number": "0x10"}},
+            })
+            .to_string();
+            (slot, text)
+        };
+        let (slot, text) = head(requests[0].0, "0xaa");
+        let effects = state.on_upstream_message(slot, &text);
+        match effects.as_slice() {
+            [Effect::Client(1, notification)] => {
+                assert_eq!(notification["params"]["subscription"], "0x1")
+            }
+            other => panic!("Unexpected effects: {:?}", other),
+        }
+        let (slot, text) = head(requests[1].0, "0xbb");
+        assert!(state.on_upstream_message(slot, &text).is_empty());
+
+        // Losing one slot keeps the subscription alive on the other without a gap
+        state.on_upstream_lost(requests[0].0);
+        let (slot, text) = head(requests[1].0, "0xbb");
+        assert!(state.on_upstream_message(slot, &text).is_empty());
+        assert_eq!(state.abandon_orphans(), Vec::new());
+    }
+
+    #[test]
+    fn test_reconfigure_redundancy() {
+        let mut state = HubState::new(PubSubProtocol::Ethereum, 2, true);
+        state.register(1);
+        let requests: Vec<(Slot, Value)> = state
+            .on_client_request(
+                1,
+                json!({"jsonrpc":"2.0","id":1,"method":"eth_subscribe","params":["newHeads"]}),
+            )
+            .into_iter()
+            .map(|effect| match effect {
+                Effect::Upstream(slot, request) => (slot, request),
+                other => panic!("Unexpected effect: {:?}", other),
+            })
+            .collect();
+        assert_eq!(requests.len(), 2);
+        for (slot, request) in &requests {
+            let response =
+                json!({"jsonrpc":"2.0","id":request["id"],"result":format!("0x{}", slot)});
+            state.on_upstream_message(*slot, &response.to_string());
+        }
+
+        // The subscription stays on the remaining slot without a gap
+        state.reconfigure(1, true);
+        let notification = json!({
+            "jsonrpc": "2.0",
+            "method": "eth_subscription",
+            "params": {"subscription": "0x0", "result": {"hash": "0x01", "number": "0x10"}},
+        });
+        assert_eq!(
+            state
+                .on_upstream_message(0, &notification.to_string())
+                .len(),
+            1
+        );
+        assert_eq!(state.abandon_orphans(), Vec::new());
+
+        // New subscriptions are held on the configured number of slots
+        let request = json!({"jsonrpc":"2.0","id":2,"method":"eth_subscribe","params":["logs"]});
+        assert_eq!(state.on_client_request(1, request.clone()).len(), 1);
+        state.reconfigure(3, true);
+        let request = json!({"jsonrpc":"2.0","id":3,"method":"eth_subscribe","params":["newPendingTransactions"]});
+        assert_eq!(state.on_client_request(1, request).len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_upstream_does_not_block_clients() {
+        // Accepts connections but never completes the WebSocket handshake
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                held.push(stream);
+            }
+        });
+
+        let provider = Arc::new(Provider::from_json(
+            json!({"solana-devnet": {"http": ["http://127.0.0.1:1"], "ws": [format!("ws://{}", address)]}}),
+        ));
+
+        let state = HubState::new(PubSubProtocol::Solana, 1, false);
+        let (commands, commands_rx) = mpsc::unbounded_channel();
+        tokio::spawn(HubTask::new(Network::SOLANA_DEVNET, provider, state).run(commands_rx));
+        let (tx1, _rx1) = mpsc::channel(CLIENT_BUFFER);
+        let (tx2, mut rx2) = mpsc::channel(CLIENT_BUFFER);
+        commands
+            .send(Command::Register { client: 1, tx: tx1 })
+            .unwrap();
+        commands
+            .send(Command::Register { client: 2, tx: tx2 })
+            .unwrap();
+        commands
+            .send(Command::Request {
+                client: 1,
+                request: json!({"jsonrpc":"2.0","id":1,"method":"slotSubscribe"}),
+            })
+            .unwrap();
+
+        // The hub keeps serving other clients while the upstream is being dialed
+        commands
+            .send(Command::Request {
+                client: 2,
+                request: json!({"jsonrpc":"2.0","id":2,"method":"slotUnsubscribe","params":[9]}),
+            })
+            .unwrap();
+        let response = tokio::time::timeout(Duration::from_secs(1), rx2.recv())
+            .await
+            .expect("Hub blocked while connecting")
+            .unwrap();
+        assert_eq!(response["error"]["message"], "Invalid subscription id");
+    }
+
+    #[tokio::test]
+    async fn test_slow_client_is_disconnected() {
+        let provider = Arc::new(Provider::from_json(json!({})));
+        let state = HubState::new(PubSubProtocol::Solana, 1, false);
+        let mut task = HubTask::new(Network::SOLANA_DEVNET, provider, state);
+        let (tx, mut rx) = mpsc::channel(1);
+        task.clients.insert(1, tx);
+
+        task.apply(vec![
+            Effect::Client(1, json!(1)),
+            Effect::Client(1, json!(2)),
+            Effect::Client(1, json!(3)),
+        ])
+        .await;
+        assert!(!task.clients.contains_key(&1));
+        assert_eq!(rx.recv().await, Some(json!(1)));
+        assert_eq!(rx.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_redials_abandoned_slot() {
+        let provider = Arc::new(Provider::from_json(
+            json!({"solana-devnet": {"http": ["http://127.0.0.1:1"], "ws": ["ws://127.0.0.1:1"]}}),
+        ));
+        let state = HubState::new(PubSubProtocol::Solana, 1, false);
+        let mut task = HubTask::new(Network::SOLANA_DEVNET, provider, state);
+        task.state.register(1);
+        let request = json!({"jsonrpc":"2.0","id":1,"method":"slotSubscribe"});
+        upstream_request(task.state.on_client_request(1, request));
+
+        // A failed dial leaves the slot alone until its redial is due
+        task.slots[0].dialing = Some(0);
+        task.on_connected(0, 0, None).await;
+        assert_eq!(task.slots[0].redials, 1);
+        task.state.register(2);
+        let request = json!({"jsonrpc":"2.0","id":2,"method":"slotSubscribe"});
+        upstream_request(task.state.on_client_request(2, request));
+        task.heartbeat().await;
+        assert!(task.slots[0].dialing.is_none());
+
+        task.slots[0].redial_at = Some(Instant::now());
+        task.heartbeat().await;
+        assert!(task.slots[0].dialing.is_some());
+    }
+}
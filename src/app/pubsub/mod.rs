@@ -0,0 +1,7 @@
+pub mod hub;
+pub mod protocol;
+pub mod session;
+
+pub use hub::*;
+pub use protocol::*;
+pub use session::*;
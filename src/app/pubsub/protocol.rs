@@ -0,0 +1,88 @@
+use serde_json::{json, Value};
+
+/// PubSub dialects the proxy knows how to relay.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PubSubProtocol {
+    /// Solana PubSub: `xSubscribe` / `xUnsubscribe` / `xNotification`, integer subscription ids.
+    Solana,
+    /// EVM `eth_subscribe` / `eth_unsubscribe` / `eth_subscription`, hex string subscription ids.
+    Ethereum,
+}
+
+impl PubSubProtocol {
+    pub fn is_subscribe(self, method: &str) -> bool {
+        match self {
+            PubSubProtocol::Solana => method.ends_with("Subscribe"),
+            PubSubProtocol::Ethereum => method == "eth_subscribe",
+        }
+    }
+
+    pub fn is_unsubscribe(self, method: &str) -> bool {
+        match self {
+            PubSubProtocol::Solana => method.ends_with("Unsubscribe"),
+            PubSubProtocol::Ethereum => method == "eth_unsubscribe",
+        }
+    }
+
+    pub fn unsubscribe_method(self, subscribe_method: &str) -> String {
+        match self {
+            PubSubProtocol::Solana => subscribe_method.replace("Subscribe", "Unsubscribe"),
+            PubSubProtocol::Ethereum => "eth_unsubscribe".to_string(),
+        }
+    }
+
+    /// Subscriptions the upstream drops on its own after the first notification.
+    pub fn is_one_shot_notification(self, method: &str) -> bool {
+        match self {
+            PubSubProtocol::Solana => method == "signatureNotification",
+            PubSubProtocol::Ethereum => false,
+        }
+    }
+
+    pub fn format_subscription_id(self, id: u64) -> Value {
+        match self {
+            PubSubProtocol::Solana => json!(id),
+            PubSubProtocol::Ethereum => json!(format!("0x{:x}", id)),
+        }
+    }
+
+    pub fn parse_subscription_id(self, id: &Value) -> Option<u64> {
+        match self {
+            PubSubProtocol::Solana => id.as_u64(),
+            PubSubProtocol::Ethereum => id
+                .as_str()
+                .and_then(|id| u64::from_str_radix(id.trim_start_matches("0x"), 16).ok()),
+        }
+    }
+
+    /// Identity of a notification result, equal for the same event delivered by different
+    /// upstream nodes.
+    pub fn dedup_key(self, result: &Value) -> String {
+        match self {
+            PubSubProtocol::Solana => result.to_string(),
+            PubSubProtocol::Ethereum => match result {
+                // newPendingTransactions without full transactions
+                Value::String(hash) => hash.clone(),
+                // logs
+                Value::Object(log) if log.contains_key("logIndex") => format!(
+                    "{}:{}:{}",
+                    log.get("blockHash").unwrap_or(&Value::Null),
+                    log.get("logIndex").unwrap_or(&Value::Null),
+                    log.get("removed").unwrap_or(&Value::Null),
+                ),
+                // newHeads and full pending transactions
+                Value::Object(object) if object.contains_key("hash") => object["hash"].to_string(),
+                other => other.to_string(),
+            },
+        }
+    }
+}
+
+/// Upstream subscription ids are numbers or strings depending on the node; this turns
+/// them into a stable map key.
+pub fn subscription_key(id: &Value) -> String {
+    match id {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
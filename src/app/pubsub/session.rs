@@ -0,0 +1,353 @@
+use crate::app::pubsub::hub::Hub;
+use crate::app::pubsub::protocol::PubSubProtocol;
+use crate::app::rate_limit::SocketLimit;
+use crate::provider::{Network, Provider, ProxyProvider};
+use crate::utils::jsonrpc::rpc_error;
+use axum::body::Body;
+use axum::extract::ws::{Message, WebSocket};
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{Method, Request};
+use futures_util::{SinkExt, StreamExt};
+use http_body_util::BodyExt;
+use log::{debug, warn};
+use serde_json::Value;
+use std::sync::Arc;
+use tokio::sync::{mpsc, Semaphore};
+
+/// Calls of one socket sent upstream at once; further calls wait for one to finish.
+const MAX_IN_FLIGHT_CALLS: usize = 32;
+
+/// What to do with a message received from the client.
+#[derive(Debug, PartialEq)]
+enum ClientAction {
+    /// Answer directly on the socket.
+    Reply(Value),
+    /// Subscribe or unsubscribe through the network's hub.
+    Subscription(Value),
+    /// Any other JSON-RPC call (or batch), routed to the HTTP nodes.
+    Call(Value),
+}
+
+/// One client WebSocket attached to the subscription hub of its network.
+pub struct Session;
+
+impl Session {
+    /// Serves a client socket until the client disconnects or the hub drops it: subscriptions
+    /// go through the network's hub, every other call through the HTTP node pipeline. Calls
+    /// and subscribes are charged to the client's rate limit if it has one.
+    pub async fn run(
+        protocol: PubSubProtocol,
+        network: Network,
+        provider: Arc<Provider>,
+        proxy_provider: Arc<ProxyProvider>,
+        socket: WebSocket,
+        limit: Option<SocketLimit>,
+    ) {
+        let hub = Hub::get(network, protocol, provider.clone());
+        let (client, mut hub_rx) = hub.register();
+        let (calls_tx, mut calls_rx) = mpsc::unbounded_channel();
+        let in_flight = Arc::new(Semaphore::new(MAX_IN_FLIGHT_CALLS));
+        let (mut client_tx, mut client_rx) = socket.split();
+        debug!("{} WebSocket client {} connected", network, client);
+
+        loop {
+            let reply = tokio::select! {
+                // Stops reading the socket while the calls in flight are at their limit;
+                // hub messages and call responses are still delivered meanwhile
+                msg = client_rx.next(), if in_flight.available_permits() > 0 => match msg {
+                    Some(Ok(Message::Text(text))) => match Self::on_client_message(protocol, &text) {
+                        ClientAction::Reply(reply) => reply,
+                        ClientAction::Subscription(request) => {
+                            let charged =
+                                Self::charge_subscription(protocol, network, limit.as_ref(), &request);
+                            match charged {
+                                Ok(()) => {
+                                    hub.request(client, request);
+                                    continue;
+                                }
+                                Err(reply) => reply,
+                            }
+                        }
+                        ClientAction::Call(request) => {
+                            // Only this loop takes permits, and the arm runs with one free
+                            let permit = in_flight.clone().try_acquire_owned().unwrap();
+                            let calls_tx = calls_tx.clone();
+                            let provider = provider.clone();
+                            let proxy_provider = proxy_provider.clone();
+                            let limit = limit.clone();
+                            tokio::spawn(async move {
+                                let response = Self::call(
+                                    network,
+                                    provider,
+                                    proxy_provider,
+                                    limit,
+                                    request,
+                                )
+                                .await;
+                                drop(permit);
+                                let _ = calls_tx.send(response);
+                            });
+                            continue;
+                        }
+                    },
+                    Some(Ok(Message::Close(_))) | None => break,
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => {
+                        debug!("Client WebSocket error: {:?}", e);
+                        break;
+                    }
+                },
+                msg = hub_rx.recv() => match msg {
+                    Some(message) => message,
+                    None => break,
+                },
+                Some(response) = calls_rx.recv() => response,
+            };
+
+            if client_tx
+                .send(Message::Text(reply.to_string()))
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+
+        hub.disconnect(client);
+        let _ = client_tx.send(Message::Close(None)).await;
+        debug!("{} WebSocket client {} disconnected", network, client);
+    }
+
+    fn on_client_message(protocol: PubSubProtocol, text: &str) -> ClientAction {
+        let request: Value = match serde_json::from_str(text) {
+            Ok(request) => request,
+            Err(_) => return ClientAction::Reply(rpc_error(Value::Null, -32700, "Parse error")),
+        };
+        if request.is_array() {
+            return ClientAction::Call(request);
+        }
+
+        let request_id = request.get("id").cloned().unwrap_or(Value::Null);
+        match request.get("method").and_then(Value::as_str) {
+            Some(method) if protocol.is_subscribe(method) || protocol.is_unsubscribe(method) => {
+                ClientAction::Subscription(request)
+            }
+            Some(_) => ClientAction::Call(request),
+            None => ClientAction::Reply(rpc_error(request_id, -32600, "Invalid request")),
+        }
+    }
+
+    /// Charges a subscribe call to the client's rate limit like any other call;
+    /// unsubscribing is free.
+    fn charge_subscription(
+        protocol: PubSubProtocol,
+        network: Network,
+        limit: Option<&SocketLimit>,
+        request: &Value,
+    ) -> Result<(), Value> {
+        let Some(limit) = limit else {
+            return Ok(());
+        };
+        let subscribe = request
+            .get("method")
+            .and_then(Value::as_str)
+            .is_some_and(|method| protocol.is_subscribe(method));
+        if subscribe
+            && limit
+                .check(network.as_ref(), request.to_string().as_bytes())
+                .is_err()
+        {
+            let request_id = request.get("id").cloned().unwrap_or(Value::Null);
+            return Err(rpc_error(request_id, -32005, "Rate limit exceeded"));
+        }
+        Ok(())
+    }
+
+    /// Sends a call through the same rate limit, node selection, retry and proxy
+    /// logic as `POST /rpc/{network}` and returns the JSON-RPC response.
+    async fn call(
+        network: Network,
+        provider: Arc<Provider>,
+        proxy_provider: Arc<ProxyProvider>,
+        limit: Option<SocketLimit>,
+        request: Value,
+    ) -> Value {
+        let request_id = request.get("id").cloned().unwrap_or(Value::Null);
+        let body = request.to_string();
+        if let Some(limit) = limit {
+            if limit.check(network.as_ref(), body.as_bytes()).is_err() {
+                return rpc_error(request_id, -32005, "Rate limit exceeded");
+            }
+        }
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(format!("/rpc/{}", network))
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .unwrap();
+
+        let response = network.handle_request(provider, proxy_provider, req).await;
+        let status = response.status();
+        let body = match response.into_body().collect().await {
+            Ok(body) => body.to_bytes(),
+            Err(e) => {
+                warn!("Error reading {} response: {:?}", network, e);
+                return rpc_error(request_id, -32603, "Error reading upstream response");
+            }
+        };
+
+        serde_json::from_slice(&body).unwrap_or_else(|_| {
+            warn!("Non JSON-RPC {} response. Status: {}", network, status);
+            rpc_error(
+                request_id,
+                -32603,
+                &format!("Upstream error. Status: {}", status),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::rate_limit::RateLimiter;
+    use axum::extract::ws::WebSocketUpgrade;
+    use axum::routing::{get, post};
+    use axum::{Json, Router};
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::{connect_async, tungstenite};
+
+    async fn serve(router: Router) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        address.to_string()
+    }
+
+    #[tokio::test]
+    async fn test_in_flight_calls_are_bounded() {
+        // A slow node recording how many calls it serves at once
+        let calls = Arc::new((AtomicUsize::new(0), AtomicUsize::new(0)));
+        let node_calls = calls.clone();
+        let node = serve(Router::new().route(
+            "/",
+            post(move |Json(request): Json<Value>| {
+                let calls = node_calls.clone();
+                async move {
+                    let current = calls.0.fetch_add(1, Ordering::SeqCst) + 1;
+                    calls.1.fetch_max(current, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    calls.0.fetch_sub(1, Ordering::SeqCst);
+                    Json(json!({"jsonrpc": "2.0", "id": request["id"], "result": "0x1"}))
+                }
+            }),
+        ))
+        .await;
+
+        let provider = Arc::new(Provider::from_json(
+            json!({"bsc-testnet": [format!("http://{}", node)]}),
+        ));
+        let proxy_provider =
+            Arc::new(ProxyProvider::new(String::new(), false, Duration::ZERO).unwrap());
+        let proxy = serve(Router::new().route(
+            "/ws",
+            get(move |ws: WebSocketUpgrade| async move {
+                ws.on_upgrade(move |socket| {
+                    Session::run(
+                        PubSubProtocol::Ethereum,
+                        Network::BSC_TESTNET,
+                        provider,
+                        proxy_provider,
+                        socket,
+                        None,
+                    )
+                })
+            }),
+        ))
+        .await;
+
+        let (mut socket, _) = connect_async(format!("ws://{}/ws", proxy)).await.unwrap();
+        let flood = 2 * MAX_IN_FLIGHT_CALLS;
+        for id in 0..flood {
+            let call = json!({"jsonrpc": "2.0", "id": id, "method": "eth_blockNumber"});
+            socket
+                .send(tungstenite::Message::Text(call.to_string()))
+                .await
+                .unwrap();
+        }
+        let mut answered = 0;
+        while answered < flood {
+            if let tungstenite::Message::Text(text) = socket.next().await.unwrap().unwrap() {
+                assert_eq!(
+                    serde_json::from_str::<Value>(&text).unwrap()["result"],
+                    "0x1"
+                );
+                answered += 1;
+            }
+        }
+
+        let max_in_flight = calls.1.load(Ordering::SeqCst);
+        assert!(max_in_flight > 1);
+        assert!(max_in_flight <= MAX_IN_FLIGHT_CALLS);
+    }
+
+    #[test]
+    fn test_subscriptions_are_charged() {
+        let limiter = Arc::new(RateLimiter::new(Some(
+            serde_json::from_str(r#"{"rules": [{"requests_per_second": 0.01, "burst": 1}]}"#)
+                .unwrap(),
+        )));
+        let limit = SocketLimit::new(limiter, vec!["key:alice".to_string()]);
+        let charge = |request: Value| {
+            Session::charge_subscription(
+                PubSubProtocol::Ethereum,
+                Network::BSC_TESTNET,
+                Some(&limit),
+                &request,
+            )
+        };
+        let subscribe =
+            |id: u64| json!({"id": id, "method": "eth_subscribe", "params": ["newHeads"]});
+        let unsubscribe = json!({"id": 3, "method": "eth_unsubscribe", "params": ["0x1"]});
+
+        assert_eq!(charge(subscribe(1)), Ok(()));
+        let rejected = charge(subscribe(2)).unwrap_err();
+        assert_eq!(rejected["id"], 2);
+        assert_eq!(rejected["error"]["code"], -32005);
+        assert_eq!(charge(unsubscribe), Ok(()));
+    }
+
+    #[test]
+    fn test_client_message_routing() {
+        assert_eq!(
+            Session::on_client_message(
+                PubSubProtocol::Solana,
+                r#"{"jsonrpc":"2.0","id":1,"method":"getBalance","params":["Acc"]}"#,
+            ),
+            ClientAction::Call(
+                json!({"jsonrpc":"2.0","id":1,"method":"getBalance","params":["Acc"]})
+            )
+        );
+        assert!(matches!(
+            Session::on_client_message(
+                PubSubProtocol::Ethereum,
+                r#"{"jsonrpc":"2.0","id":1,"method":"eth_subscribe","params":["newHeads"]}"#,
+            ),
+            ClientAction::Subscription(_)
+        ));
+        assert!(matches!(
+            Session::on_client_message(
+                PubSubProtocol::Ethereum,
+                r#"[{"jsonrpc":"2.0","id":1,"method":"eth_blockNumber"}]"#,
+            ),
+            ClientAction::Call(_)
+        ));
+        assert!(matches!(
+            Session::on_client_message(PubSubProtocol::Solana, r#"{"id":3}"#),
+            ClientAction::Reply(_)
+        ));
+    }
+}
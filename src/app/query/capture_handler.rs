@@ -0,0 +1,25 @@
+use crate::app::capture;
+use axum::extract::Path;
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+
+/// Captured exchanges, newest first.
+pub async fn captures_handler() -> Response {
+    Json(capture::list()).into_response()
+}
+
+/// A single capture with its bodies and headers, as a file download.
+pub async fn capture_handler(Path(id): Path<u64>) -> Response {
+    match capture::get(id) {
+        Some(capture) => (
+            [(
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"capture-{}.json\"", id),
+            )],
+            Json(capture),
+        )
+            .into_response(),
+        None => (StatusCode::NOT_FOUND, "Capture not found").into_response(),
+    }
+}
@@ -0,0 +1,6 @@
+use axum::response::Html;
+
+/// Self-contained page polling `/status` for live node and proxy statistics.
+pub async fn dashboard_handler() -> Html<&'static str> {
+    Html(include_str!("dashboard.html"))
+}
@@ -0,0 +1,111 @@
+use crate::app::events::{self, EventFilter};
+use axum::body::Body;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::http::header::AUTHORIZATION;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::Response;
+use log::{debug, warn};
+use serde::Deserialize;
+use serde_json::json;
+use subtle::ConstantTimeEq;
+use tokio::sync::broadcast::error::RecvError;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct EventStreamQuery {
+    pub token: Option<String>,
+    pub types: Option<String>,
+    pub networks: Option<String>,
+}
+
+/// Upgrades to a read-only stream of proxy events. The subscriber authenticates with the
+/// configured token (`Authorization: Bearer` or `?token=`) and picks event types and
+/// networks with the `types` and `networks` query parameters; unknown types are
+/// answered with 400.
+pub async fn events_handler(
+    ws: WebSocketUpgrade,
+    query: EventStreamQuery,
+    headers: HeaderMap,
+    events_token: Option<String>,
+) -> Response {
+    let Some(events_token) = events_token else {
+        return Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body(Body::from("Event stream is disabled"))
+            .unwrap();
+    };
+
+    let provided = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(String::from)
+        .or(query.token);
+    // Compared in constant time so response timing does not reveal the token
+    let valid = provided
+        .is_some_and(|provided| bool::from(provided.as_bytes().ct_eq(events_token.as_bytes())));
+    if !valid {
+        warn!("Rejected event stream subscriber: invalid token");
+        return Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .body(Body::from("Unauthorized"))
+            .unwrap();
+    }
+
+    let filter = match EventFilter::from_query(query.types.as_deref(), query.networks.as_deref()) {
+        Ok(filter) => filter,
+        Err(e) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(e))
+                .unwrap()
+        }
+    };
+    ws.on_upgrade(move |socket| stream_events(socket, filter))
+}
+
+/// Sends matching events until the subscriber leaves. A text message with a JSON
+/// `{"types": [...], "networks": [...]}` object replaces the filter; anything else is
+/// answered with an `{"error": ...}` message and leaves the filter as it was.
+async fn stream_events(mut socket: WebSocket, mut filter: EventFilter) {
+    let mut rx = events::subscribe();
+    debug!("Event stream subscriber connected. Filter: {:?}", filter);
+
+    loop {
+        tokio::select! {
+            msg = socket.recv() => match msg {
+                Some(Ok(Message::Text(text))) => match serde_json::from_str(&text) {
+                    Ok(new_filter) => {
+                        debug!("Event stream filter changed: {:?}", new_filter);
+                        filter = new_filter;
+                    }
+                    Err(e) => {
+                        debug!("Rejected event stream filter: {}", e);
+                        let reply = json!({"error": format!("Invalid filter: {}", e)});
+                        if socket.send(Message::Text(reply.to_string())).await.is_err() {
+                            break;
+                        }
+                    }
+                },
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+            event = rx.recv() => match event {
+                Ok(envelope) => {
+                    if !filter.matches(&envelope.event) {
+                        continue;
+                    }
+                    let text = serde_json::to_string(&envelope).unwrap();
+                    if socket.send(Message::Text(text)).await.is_err() {
+                        break;
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Event stream subscriber lagged, {} events skipped", skipped);
+                }
+                Err(RecvError::Closed) => break,
+            },
+        }
+    }
+
+    debug!("Event stream subscriber disconnected");
+}
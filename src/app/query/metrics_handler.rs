@@ -0,0 +1,12 @@
+use crate::app::metrics;
+use axum::http::header::CONTENT_TYPE;
+use axum::response::Response;
+use axum::{body::Body, http::StatusCode};
+
+pub async fn metrics_handler() -> Response {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+        .body(Body::from(metrics::render()))
+        .unwrap()
+}
@@ -1,5 +1,17 @@
+pub mod capture_handler;
+pub mod dashboard_handler;
+pub mod events_handler;
 pub mod fallback_handler;
+pub mod metrics_handler;
 pub mod network_handler;
+pub mod status_handler;
+pub mod ws_network_handler;
 
+pub use capture_handler::*;
+pub use dashboard_handler::*;
+pub use events_handler::*;
 pub use fallback_handler::*;
+pub use metrics_handler::*;
 pub use network_handler::*;
+pub use status_handler::*;
+pub use ws_network_handler::*;
@@ -1,6 +1,8 @@
+use crate::app::access_log::AccessContext;
+use crate::provider::proxy::{Proxy, Upstream};
 use crate::provider::ProxyProvider;
 use crate::provider::{Network, Provider};
-use axum::extract::State;
+use axum::extract::{Path, State};
 use axum::response::Response;
 use axum::{body::Body, extract::Request, http::StatusCode};
 use log::{debug, error};
@@ -9,14 +11,14 @@ use std::sync::Arc;
 
 pub async fn network_handler(
     State((provider, proxy_provider)): State<(Arc<Provider>, Arc<ProxyProvider>)>,
+    Path(network): Path<String>,
     req: Request<Body>,
 ) -> Response {
-    let path = req.uri().path();
-    let network = path.split('/').last().unwrap_or("");
-
     match Network::from_str(&network) {
         Ok(network) => {
             debug!("Handling request for network: {:?}", network);
+            AccessContext::from_extensions(req.extensions())
+                .update(|details| details.network = Some(network.to_string()));
             network.handle_request(provider, proxy_provider, req).await
         }
         Err(_) => {
@@ -28,3 +30,45 @@ pub async fn network_handler(
         }
     }
 }
+
+/// Passes any method on `/rpc/{network}/{*path}` through to the sub-path of a
+/// node's base URL, query string included.
+pub async fn network_path_handler(
+    State((provider, proxy_provider)): State<(Arc<Provider>, Arc<ProxyProvider>)>,
+    Path((network, _)): Path<(String, String)>,
+    req: Request<Body>,
+) -> Response {
+    let Ok(network) = Network::from_str(&network) else {
+        error!("Invalid network: {}", network);
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(Body::from("Invalid network"))
+            .unwrap();
+    };
+
+    AccessContext::from_extensions(req.extensions())
+        .update(|details| details.network = Some(network.to_string()));
+
+    // The raw path keeps the client's percent-encoding; the extracted one is decoded
+    let uri = req.uri();
+    let Some(path) = uri.path().splitn(4, '/').nth(3) else {
+        // Only the API key followed the network
+        return network.handle_request(provider, proxy_provider, req).await;
+    };
+    let path = match uri.query() {
+        Some(query) => format!("{}?{}", path, query),
+        None => path.to_string(),
+    };
+    debug!(
+        "Handling {} {} request for network: {:?}",
+        req.method(),
+        path,
+        network
+    );
+
+    let upstream = Upstream {
+        path: Some(path),
+        ..Default::default()
+    };
+    Proxy::forward(network, provider, proxy_provider, req, upstream).await
+}
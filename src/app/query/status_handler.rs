@@ -0,0 +1,43 @@
+use crate::provider::{Network, Provider, ProxyProvider};
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde_json::{json, Map, Value};
+use std::str::FromStr;
+use std::sync::Arc;
+
+type AdminState = State<(Arc<Provider>, Arc<ProxyProvider>)>;
+
+/// Every network's nodes and every proxy.
+pub async fn status_handler(State((provider, proxy_provider)): AdminState) -> Response {
+    let networks: Map<String, Value> = Network::all()
+        .into_iter()
+        .map(|network| (network, provider.status(network)))
+        .filter(|(_, nodes)| !nodes.is_empty())
+        .map(|(network, nodes)| {
+            let status = json!({"family": network.family(), "nodes": nodes});
+            (network.to_string(), status)
+        })
+        .collect();
+    Json(json!({"networks": networks, "proxies": proxy_provider.status()})).into_response()
+}
+
+/// The nodes of a single network.
+pub async fn network_status_handler(
+    State((provider, _)): AdminState,
+    Path(network): Path<String>,
+) -> Response {
+    match Network::from_str(&network) {
+        Ok(network) => Json(json!({
+            "family": network.family(),
+            "nodes": provider.status(network),
+        }))
+        .into_response(),
+        Err(_) => (StatusCode::NOT_FOUND, "Invalid network").into_response(),
+    }
+}
+
+pub async fn proxies_status_handler(State((_, proxy_provider)): AdminState) -> Response {
+    Json(proxy_provider.status()).into_response()
+}
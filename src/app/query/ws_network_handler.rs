@@ -0,0 +1,34 @@
+use crate::app::rate_limit::SocketLimit;
+use crate::provider::ProxyProvider;
+use crate::provider::{Network, Provider};
+use axum::extract::ws::WebSocketUpgrade;
+use axum::extract::{Extension, Path, State};
+use axum::response::Response;
+use axum::{body::Body, http::StatusCode};
+use log::{debug, error};
+use std::str::FromStr;
+use std::sync::Arc;
+
+pub async fn ws_network_handler(
+    State((provider, proxy_provider)): State<(Arc<Provider>, Arc<ProxyProvider>)>,
+    Path(network): Path<String>,
+    limit: Option<Extension<SocketLimit>>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    let limit = limit.map(|Extension(limit)| limit);
+    match Network::from_str(&network) {
+        Ok(network) => {
+            debug!("Handling WebSocket connection for network: {:?}", network);
+            ws.on_upgrade(move |socket| {
+                network.handle_socket(provider, proxy_provider, socket, limit)
+            })
+        }
+        Err(_) => {
+            error!("Invalid network: {}", network);
+            Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from("Invalid network"))
+                .unwrap()
+        }
+    }
+}
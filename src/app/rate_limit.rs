@@ -0,0 +1,523 @@
+use crate::app::auth::ApiKeyName;
+use crate::utils::jsonrpc::{rpc_error, rpc_methods};
+use axum::body::Body;
+use axum::extract::{ConnectInfo, Request};
+use axum::http::header::RETRY_AFTER;
+use axum::http::{Extensions, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Json, Response};
+use http_body_util::BodyExt;
+use log::warn;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use strum_macros::Display;
+
+/// How often buckets that have refilled completely are dropped.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+/// Identity of clients none of the configured identities apply to.
+const ANONYMOUS: &str = "anonymous";
+
+/// Kind of JSON-RPC method a limit applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum MethodClass {
+    Read,
+    Write,
+    Heavy,
+}
+
+/// What a client is told apart by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientIdentity {
+    /// The name of the API key the request was authenticated with.
+    Key,
+    Ip,
+}
+
+/// A token bucket per client for the requests of a network and method class.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct RateLimitRule {
+    /// Network the rule applies to; unset applies it to all of them.
+    #[serde(default)]
+    pub network: Option<String>,
+    /// Method class the rule applies to; unset applies it to all of them.
+    #[serde(default)]
+    pub class: Option<MethodClass>,
+    pub requests_per_second: f64,
+    /// Requests allowed at once after idling; defaults to `requests_per_second`.
+    #[serde(default)]
+    pub burst: Option<f64>,
+}
+
+impl RateLimitRule {
+    fn burst(&self) -> f64 {
+        self.burst.unwrap_or(self.requests_per_second).max(1.0)
+    }
+
+    /// How closely the rule matches, if it does at all; the closest one applies.
+    fn specificity(&self, network: &str, class: MethodClass) -> Option<u8> {
+        let network = match &self.network {
+            Some(name) if name == network => 2,
+            Some(_) => return None,
+            None => 0,
+        };
+        let class = match self.class {
+            Some(rule_class) if rule_class == class => 1,
+            Some(_) => return None,
+            None => 0,
+        };
+        Some(network + class)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// Identities each holding their own buckets; a request must fit into all of them.
+    pub clients: Vec<ClientIdentity>,
+    pub write_methods: Vec<String>,
+    pub heavy_methods: Vec<String>,
+    pub rules: Vec<RateLimitRule>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            clients: vec![ClientIdentity::Key, ClientIdentity::Ip],
+            write_methods: [
+                "sendTransaction",
+                "requestAirdrop",
+                "eth_sendRawTransaction",
+                "eth_sendTransaction",
+            ]
+            .map(String::from)
+            .to_vec(),
+            heavy_methods: [
+                "getProgramAccounts",
+                "getBlock",
+                "getSignaturesForAddress",
+                "getTokenLargestAccounts",
+                "eth_getLogs",
+                "debug_traceTransaction",
+                "debug_traceBlockByNumber",
+                "trace_block",
+                "trace_filter",
+            ]
+            .map(String::from)
+            .to_vec(),
+            rules: Vec::new(),
+        }
+    }
+}
+
+impl RateLimitConfig {
+    fn class(&self, method: &str) -> MethodClass {
+        if self.write_methods.iter().any(|m| m == method) {
+            MethodClass::Write
+        } else if self.heavy_methods.iter().any(|m| m == method) {
+            MethodClass::Heavy
+        } else {
+            MethodClass::Read
+        }
+    }
+
+    /// Index of the rule limiting `class` calls on `network`.
+    fn rule(&self, network: &str, class: MethodClass) -> Option<usize> {
+        self.rules
+            .iter()
+            .enumerate()
+            .filter_map(|(index, rule)| Some((rule.specificity(network, class)?, index)))
+            .max_by_key(|(specificity, index)| (*specificity, std::cmp::Reverse(*index)))
+            .map(|(_, index)| index)
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+#[derive(Debug)]
+struct Buckets {
+    buckets: HashMap<(String, usize), Bucket>,
+    swept: Instant,
+}
+
+/// Token buckets of every client, by identity and rule. Without a config
+/// nothing is limited.
+#[derive(Debug)]
+pub struct RateLimiter {
+    config: RwLock<Option<RateLimitConfig>>,
+    buckets: Mutex<Buckets>,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+impl RateLimiter {
+    pub fn new(config: Option<RateLimitConfig>) -> Self {
+        RateLimiter {
+            config: RwLock::new(config),
+            buckets: Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                swept: Instant::now(),
+            }),
+        }
+    }
+
+    /// Applies a new config, starting every client over with full buckets.
+    pub fn reload(&self, config: Option<RateLimitConfig>) {
+        let mut current = self.config.write().unwrap();
+        self.buckets.lock().unwrap().buckets.clear();
+        *current = config;
+    }
+
+    /// Takes a token per call from every bucket involved, or none at all when
+    /// one of them runs short; then returns how long until all would have enough.
+    fn acquire(
+        &self,
+        rules: &[RateLimitRule],
+        demands: &HashMap<(String, usize), f64>,
+    ) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut state = self.buckets.lock().unwrap();
+
+        if now.duration_since(state.swept) >= SWEEP_INTERVAL {
+            state.buckets.retain(|(_, rule), bucket| {
+                let rule = &rules[*rule];
+                let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+                bucket.tokens + elapsed * rule.requests_per_second < rule.burst()
+            });
+            state.swept = now;
+        }
+
+        let mut wait = Duration::ZERO;
+        for ((identity, index), count) in demands {
+            let rule = &rules[*index];
+            let bucket = state
+                .buckets
+                .entry((identity.clone(), *index))
+                .or_insert(Bucket {
+                    tokens: rule.burst(),
+                    updated: now,
+                });
+            let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * rule.requests_per_second).min(rule.burst());
+            bucket.updated = now;
+            // A batch larger than the burst passes on a full bucket, leaving it in debt
+            let needed = count.min(rule.burst());
+            if bucket.tokens < needed {
+                let missing = needed - bucket.tokens;
+                wait = wait.max(
+                    Duration::try_from_secs_f64(missing / rule.requests_per_second)
+                        .unwrap_or(Duration::MAX),
+                );
+            }
+        }
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+
+        for ((identity, index), count) in demands {
+            if let Some(bucket) = state.buckets.get_mut(&(identity.clone(), *index)) {
+                bucket.tokens -= count;
+            }
+        }
+        Ok(())
+    }
+
+    /// Charges the JSON-RPC request `body` to every bucket of `identities`,
+    /// counting every call of a batch.
+    fn check(&self, network: &str, identities: &[String], body: &[u8]) -> Result<(), Duration> {
+        // Held until the tokens are taken so the rule indices stay valid
+        let config = self.config.read().unwrap();
+        let Some(config) = config.as_ref() else {
+            return Ok(());
+        };
+        // Requests without a JSON-RPC method count as a single read
+        let mut classes: Vec<MethodClass> = rpc_methods(body)
+            .iter()
+            .map(|method| config.class(method))
+            .collect();
+        if classes.is_empty() {
+            classes.push(MethodClass::Read);
+        }
+        let mut demands = HashMap::new();
+        for class in classes {
+            if let Some(rule) = config.rule(network, class) {
+                for identity in identities {
+                    *demands.entry((identity.clone(), rule)).or_insert(0.0) += 1.0;
+                }
+            }
+        }
+
+        self.acquire(&config.rules, &demands).inspect_err(|wait| {
+            warn!(
+                "Rate limited {} on network {} for {:?}",
+                identities.join(", "),
+                network,
+                wait
+            )
+        })
+    }
+
+    /// Identities a request's client is limited as. Clients none of them apply
+    /// to share the buckets of a single anonymous identity.
+    fn identities(&self, extensions: &Extensions) -> Vec<String> {
+        let config = self.config.read().unwrap();
+        let Some(config) = config.as_ref() else {
+            return Vec::new();
+        };
+        let identities: Vec<String> = config
+            .clients
+            .iter()
+            .filter_map(|client| match client {
+                ClientIdentity::Key => extensions
+                    .get::<ApiKeyName>()
+                    .map(|ApiKeyName(name)| format!("key:{}", name)),
+                ClientIdentity::Ip => extensions
+                    .get::<ConnectInfo<SocketAddr>>()
+                    .map(|ConnectInfo(address)| format!("ip:{}", address.ip())),
+            })
+            .collect();
+        if identities.is_empty() {
+            return vec![ANONYMOUS.to_string()];
+        }
+        identities
+    }
+}
+
+/// Buckets the calls of a `/ws/{network}` client are charged to, as identified
+/// when its connection was upgraded. Kept in the upgrade request's extensions.
+#[derive(Debug, Clone)]
+pub struct SocketLimit {
+    limiter: Arc<RateLimiter>,
+    identities: Vec<String>,
+}
+
+impl SocketLimit {
+    #[cfg(test)]
+    pub(crate) fn new(limiter: Arc<RateLimiter>, identities: Vec<String>) -> Self {
+        SocketLimit {
+            limiter,
+            identities,
+        }
+    }
+
+    /// Charges a call or batch sent over the socket.
+    pub fn check(&self, network: &str, body: &[u8]) -> Result<(), Duration> {
+        self.limiter.check(network, &self.identities, body)
+    }
+}
+
+/// Turns away `/rpc/{network}` requests with a 429 once one of the client's
+/// buckets is empty, counting every call of a batch. A `/ws/{network}` upgrade
+/// is given a [`SocketLimit`] its calls are charged to instead.
+pub async fn rate_limit(limiter: Arc<RateLimiter>, mut req: Request, next: Next) -> Response {
+    let mut segments = req.uri().path().split('/').skip(1);
+    let (socket, network) = match (segments.next(), segments.next()) {
+        (Some(kind @ ("rpc" | "ws")), Some(network)) if !network.is_empty() => {
+            (kind == "ws", network.to_string())
+        }
+        _ => return next.run(req).await,
+    };
+
+    let identities = limiter.identities(req.extensions());
+    if identities.is_empty() {
+        return next.run(req).await;
+    }
+    if socket {
+        req.extensions_mut().insert(SocketLimit {
+            limiter,
+            identities,
+        });
+        return next.run(req).await;
+    }
+
+    let (parts, body) = req.into_parts();
+    let body = match body.collect().await {
+        Ok(collected) => collected.to_bytes(),
+        Err(e) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(format!("Error: {}", e)))
+                .unwrap()
+        }
+    };
+
+    if let Err(wait) = limiter.check(&network, &identities, &body) {
+        let retry_after = wait.as_secs_f64().ceil().max(1.0) as u64;
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(RETRY_AFTER, retry_after.to_string())],
+            Json(rpc_error(Value::Null, -32005, "Rate limit exceeded")),
+        )
+            .into_response();
+    }
+    next.run(Request::from_parts(parts, Body::from(body))).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::{get, post};
+    use axum::{middleware, Extension, Router};
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_rule_selection() {
+        let config: RateLimitConfig = serde_json::from_str(
+            r#"{"rules": [
+                {"requests_per_second": 100},
+                {"class": "heavy", "requests_per_second": 5},
+                {"network": "solana", "requests_per_second": 50},
+                {"network": "solana", "class": "write", "requests_per_second": 10}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(config.class("getProgramAccounts"), MethodClass::Heavy);
+        assert_eq!(config.class("sendTransaction"), MethodClass::Write);
+        assert_eq!(config.class("getSlot"), MethodClass::Read);
+
+        assert_eq!(config.rule("ethereum", MethodClass::Read), Some(0));
+        assert_eq!(config.rule("ethereum", MethodClass::Heavy), Some(1));
+        assert_eq!(config.rule("solana", MethodClass::Heavy), Some(2));
+        assert_eq!(config.rule("solana", MethodClass::Write), Some(3));
+        assert_eq!(config.rules[1].burst(), 5.0);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit() {
+        let limiter = Arc::new(RateLimiter::new(Some(
+            serde_json::from_str(
+                r#"{"clients": ["key"], "rules": [
+                    {"class": "write", "requests_per_second": 0.01, "burst": 2}
+                ]}"#,
+            )
+            .unwrap(),
+        )));
+        let config = limiter.config.read().unwrap().clone();
+        let reloaded = limiter.clone();
+        let app = Router::new()
+            .route("/rpc/:network", post(|| async { "{}" }))
+            .layer(middleware::from_fn(move |req: Request, next: Next| {
+                rate_limit(limiter.clone(), req, next)
+            }));
+        let call = |key: &str, body: &'static str| {
+            let mut request = Request::builder()
+                .method("POST")
+                .uri("/rpc/solana")
+                .body(Body::from(body))
+                .unwrap();
+            request.extensions_mut().insert(ApiKeyName(key.to_string()));
+            app.clone().oneshot(request)
+        };
+        let send = r#"{"method":"sendTransaction"}"#;
+
+        assert_eq!(call("alice", send).await.unwrap().status(), StatusCode::OK);
+        assert_eq!(
+            call(
+                "alice",
+                r#"[{"method":"getSlot"},{"method":"sendTransaction"}]"#
+            )
+            .await
+            .unwrap()
+            .status(),
+            StatusCode::OK
+        );
+        let response = call("alice", send).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[RETRY_AFTER], "100");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"]["code"], -32005);
+
+        // Reads are unlimited, and every key has buckets of its own
+        assert_eq!(
+            call("alice", r#"{"method":"getSlot"}"#)
+                .await
+                .unwrap()
+                .status(),
+            StatusCode::OK
+        );
+        assert_eq!(call("bob", send).await.unwrap().status(), StatusCode::OK);
+
+        // A batch beyond the burst only passes on a full bucket
+        let batch = r#"[{"method":"sendTransaction"},{"method":"sendTransaction"},{"method":"sendTransaction"}]"#;
+        assert_eq!(call("carol", batch).await.unwrap().status(), StatusCode::OK);
+        assert_eq!(
+            call("carol", send).await.unwrap().status(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+
+        // Reloading starts every client over with full buckets, or lifts the limits
+        reloaded.reload(config);
+        assert_eq!(call("carol", send).await.unwrap().status(), StatusCode::OK);
+        reloaded.reload(None);
+        for _ in 0..3 {
+            assert_eq!(call("alice", send).await.unwrap().status(), StatusCode::OK);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_socket_limit() {
+        let limiter = Arc::new(RateLimiter::new(Some(
+            serde_json::from_str(
+                r#"{"clients": ["key"], "rules": [{"requests_per_second": 0.01, "burst": 2}]}"#,
+            )
+            .unwrap(),
+        )));
+        let app = Router::new()
+            .route(
+                "/ws/:network",
+                get(|limit: Option<Extension<SocketLimit>>| async move {
+                    let Some(Extension(limit)) = limit else {
+                        return "unlimited".to_string();
+                    };
+                    let call = |body: &str| limit.check("solana", body.as_bytes()).is_ok();
+                    format!(
+                        "{} {} {}",
+                        call(r#"{"method":"getSlot"}"#),
+                        call(r#"{"method":"getSlot"}"#),
+                        call(r#"[{"method":"getSlot"}]"#),
+                    )
+                }),
+            )
+            .layer(middleware::from_fn(move |req: Request, next: Next| {
+                rate_limit(limiter.clone(), req, next)
+            }));
+        let connect = |key: Option<&str>| {
+            let mut request = Request::builder()
+                .uri("/ws/solana")
+                .body(Body::empty())
+                .unwrap();
+            if let Some(key) = key {
+                request.extensions_mut().insert(ApiKeyName(key.to_string()));
+            }
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+                String::from_utf8(body.to_vec()).unwrap()
+            }
+        };
+
+        // The upgrade itself is free; the calls on the socket are charged
+        assert_eq!(connect(Some("alice")).await, "true true false");
+        assert_eq!(connect(Some("alice")).await, "false false false");
+
+        // Clients without a key share one bucket instead of going unlimited
+        assert_eq!(connect(None).await, "true true false");
+        assert_eq!(connect(None).await, "false false false");
+    }
+}
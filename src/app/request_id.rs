@@ -0,0 +1,90 @@
+use axum::extract::Request;
+use axum::http::{HeaderName, HeaderValue};
+use axum::middleware::Next;
+use axum::response::Response;
+use std::fmt;
+use tracing::Instrument;
+
+pub static REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest client supplied ID that is kept rather than replaced.
+const MAX_LEN: usize = 128;
+
+/// Identifies one client request across its upstream attempts and log lines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(String);
+
+impl RequestId {
+    fn generate() -> Self {
+        Self(hex::encode(rand::random::<[u8; 16]>()))
+    }
+
+    /// The client's `X-Request-Id` if it is usable, a new ID otherwise.
+    fn from_header(value: Option<&HeaderValue>) -> Self {
+        value
+            .and_then(|value| value.to_str().ok())
+            .filter(|id| {
+                !id.is_empty() && id.len() <= MAX_LEN && id.bytes().all(|b| b.is_ascii_graphic())
+            })
+            .map(|id| Self(id.to_string()))
+            .unwrap_or_else(Self::generate)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Assigns every request an ID, available to handlers as an extension and
+/// returned in the `X-Request-Id` response header. The header is sent upstream
+/// only with `forward` set.
+pub async fn assign_request_id(forward: bool, mut req: Request, next: Next) -> Response {
+    let id = RequestId::from_header(req.headers().get(&REQUEST_ID_HEADER));
+    let header = HeaderValue::from_str(id.as_str()).unwrap();
+    if forward {
+        req.headers_mut()
+            .insert(REQUEST_ID_HEADER.clone(), header.clone());
+    } else {
+        req.headers_mut().remove(&REQUEST_ID_HEADER);
+    }
+
+    let span = tracing::debug_span!(
+        "request",
+        request_id = %id,
+        method = %req.method(),
+    );
+    req.extensions_mut().insert(id);
+
+    let mut response = next.run(req).instrument(span).await;
+    response
+        .headers_mut()
+        .insert(REQUEST_ID_HEADER.clone(), header);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_id_from_header() {
+        let id = RequestId::from_header(Some(&HeaderValue::from_static("client-42")));
+        assert_eq!(id.as_str(), "client-42");
+
+        let generated = RequestId::from_header(None);
+        assert_eq!(generated.as_str().len(), 32);
+        assert_ne!(generated, RequestId::from_header(None));
+
+        let spaced = RequestId::from_header(Some(&HeaderValue::from_static("a b")));
+        assert_eq!(spaced.as_str().len(), 32);
+        let long = "x".repeat(MAX_LEN + 1);
+        let long = RequestId::from_header(Some(&HeaderValue::from_str(&long).unwrap()));
+        assert_eq!(long.as_str().len(), 32);
+    }
+}
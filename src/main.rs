@@ -1,27 +1,59 @@
-#[macro_use]
-mod macros;
 pub mod app;
 pub mod ports;
 pub mod provider;
 pub mod utils;
 
+use app::access_log;
+use app::auth::ApiKeys;
+use app::capture;
+use app::networks::solana::audit;
+use app::networks::verify::{verify_nodes, verify_periodically};
+use app::rate_limit::RateLimiter;
+use clap::Parser;
 use log::{error, info};
+use ports::adminapi::get_admin_router;
 use ports::httpapi::get_router;
-use provider::Provider;
 use provider::ProxyProvider;
+use provider::{Network, Provider};
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::TcpListener;
-use tokio::sync::broadcast;
-use utils::config::Config;
+use utils::config::{Cli, Config};
 use utils::logger;
 
 #[tokio::main]
 async fn main() {
-    logger::setup_logger(log::LevelFilter::Debug);
+    let cli = Cli::parse();
+    let mut config = Config::load().expect("Failed to load config");
+    cli.apply(&mut config);
 
-    let config = Config::load().expect("Failed to load config");
+    if let Err(e) = logger::setup_logger(&config.logging) {
+        panic!("Failed to set up logging: {}", e);
+    }
 
-    let provider = Arc::new(match Provider::new(config.node_list_path) {
+    if let Err(e) = Network::load(&config.networks) {
+        error!("Failed to register networks: {}", e);
+        panic!("Failed to register networks: {}", e);
+    }
+
+    if let Some(path) = &config.audit_log_path {
+        if let Err(e) = audit::init(path) {
+            error!("Failed to open audit log {}: {}", path, e);
+        }
+    }
+
+    if let Some(path) = &config.access_log_path {
+        if let Err(e) = access_log::init(path, &config.logging) {
+            error!("Failed to open access log {}: {}", path, e);
+        }
+    }
+
+    if let Some(capture_config) = &config.capture {
+        capture::init(capture_config.clone());
+    }
+
+    let provider = Arc::new(match Provider::new(config.node_list_path.clone()) {
         Ok(provider) => provider,
         Err(e) => {
             error!("Failed to initialize provider: {}", e);
@@ -30,7 +62,11 @@ async fn main() {
     });
 
     let proxy_provider = Arc::new(
-        match ProxyProvider::new(config.proxy_list_path, config.proxy_is_enabled) {
+        match ProxyProvider::new(
+            config.proxy_list_path.clone(),
+            config.proxy_is_enabled,
+            Duration::from_secs(config.proxy_cooldown_secs),
+        ) {
             Ok(proxy_provider) => proxy_provider,
             Err(e) => {
                 error!("Failed to initialize proxy provider: {}", e);
@@ -39,9 +75,47 @@ async fn main() {
         },
     );
 
-    let (tx, _rx) = broadcast::channel(100);
+    let api_keys = Arc::new(
+        match ApiKeys::new(config.api_keys_path.as_deref(), &config.api_keys) {
+            Ok(api_keys) => api_keys,
+            Err(e) => {
+                error!("Failed to load API keys: {}", e);
+                panic!("Failed to load API keys: {}", e);
+            }
+        },
+    );
+
+    let rate_limiter = Arc::new(RateLimiter::new(config.rate_limits.clone()));
+
+    // Nodes listed under the wrong chain are quarantined before serving anything
+    verify_nodes(&provider, &proxy_provider).await;
+    tokio::spawn(verify_periodically(
+        provider.clone(),
+        proxy_provider.clone(),
+        Duration::from_secs(config.node_verify_interval_secs),
+    ));
+    tokio::spawn(reload_on_hangup(
+        provider.clone(),
+        proxy_provider.clone(),
+        api_keys.clone(),
+        rate_limiter.clone(),
+        cli,
+    ));
+
+    if let Some(address) = &config.admin_server_address {
+        let admin = get_admin_router(provider.clone(), proxy_provider.clone());
+        let listener = TcpListener::bind(address)
+            .await
+            .expect("Failed to bind admin address");
+        info!("Admin API listening on {}", address);
+        tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, admin).await {
+                error!("Admin API failed: {}", e);
+            }
+        });
+    }
 
-    let app = get_router(tx, provider, proxy_provider);
+    let app = get_router(&config, provider, proxy_provider, api_keys, rate_limiter);
 
     let listener = TcpListener::bind(&config.http_server_address)
         .await
@@ -49,7 +123,57 @@ async fn main() {
 
     info!("Listening on {}", config.http_server_address);
 
-    axum::serve(listener, app)
-        .await
-        .expect("Failed to start server");
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .expect("Failed to start server");
+}
+
+/// Re-reads the log levels, network registry, API keys, rate limits and node
+/// lists whenever SIGHUP is received, verifying the nodes again afterwards.
+async fn reload_on_hangup(
+    provider: Arc<Provider>,
+    proxy_provider: Arc<ProxyProvider>,
+    api_keys: Arc<ApiKeys>,
+    rate_limiter: Arc<RateLimiter>,
+    cli: Cli,
+) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            error!("Failed to listen for SIGHUP: {}", e);
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        info!("Reloading networks and node lists");
+        let mut config = match Config::load() {
+            Ok(config) => config,
+            Err(e) => {
+                error!("Failed to reload config: {}", e);
+                continue;
+            }
+        };
+        cli.apply(&mut config);
+        if let Err(e) = logger::set_level(&config.logging.level) {
+            error!("Failed to reload log levels: {}", e);
+        }
+        if let Err(e) = Network::load(&config.networks) {
+            error!("Failed to reload networks: {}", e);
+            continue;
+        }
+        if let Err(e) = api_keys.reload(config.api_keys_path.as_deref(), &config.api_keys) {
+            error!("Failed to reload API keys: {}", e);
+        }
+        rate_limiter.reload(config.rate_limits.clone());
+        if let Err(e) = provider.reload() {
+            error!("Failed to reload node lists: {}", e);
+        }
+        verify_nodes(&provider, &proxy_provider).await;
+    }
 }
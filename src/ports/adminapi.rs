@@ -0,0 +1,97 @@
+use crate::app::query::{
+    capture_handler, captures_handler, dashboard_handler, fallback_handler, metrics_handler,
+    network_status_handler, proxies_status_handler, status_handler,
+};
+use crate::provider::{Provider, ProxyProvider};
+use axum::{routing::get, Router};
+use std::sync::Arc;
+
+/// Live state of the nodes and proxies, its dashboard, the Prometheus metrics
+/// and the captured exchanges, served on its own address.
+pub fn get_admin_router(provider: Arc<Provider>, proxy_provider: Arc<ProxyProvider>) -> Router {
+    Router::new()
+        .route("/dashboard", get(dashboard_handler))
+        .route("/status", get(status_handler))
+        .route("/status/networks/:network", get(network_status_handler))
+        .route("/status/proxies", get(proxies_status_handler))
+        .route("/captures", get(captures_handler))
+        .route("/captures/:id", get(capture_handler))
+        .route("/metrics", get(metrics_handler))
+        .fallback(fallback_handler)
+        .with_state((provider, proxy_provider))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use http_body_util::BodyExt;
+    use serde_json::Value;
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    async fn get_json(app: &Router, uri: &str) -> (StatusCode, Value) {
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    #[tokio::test]
+    async fn test_admin_status() {
+        let provider = Arc::new(Provider::from_json(serde_json::json!({
+            "solana": {"http": ["https://a.example/0123456789abcdef0123"], "ws": ["wss://a.example"]}
+        })));
+        provider.quarantine(
+            crate::provider::Network::SOLANA,
+            "wss://a.example",
+            "Genesis hash mismatch".to_string(),
+        );
+        let proxy_provider =
+            Arc::new(ProxyProvider::new(String::new(), false, Duration::from_secs(1)).unwrap());
+        let app = get_admin_router(provider, proxy_provider);
+
+        let (status, body) = get_json(&app, "/status").await;
+        assert_eq!(status, StatusCode::OK);
+        let nodes = &body["networks"]["solana"]["nodes"];
+        assert_eq!(body["networks"]["solana"]["family"], "solana");
+        assert_eq!(nodes[0]["node"], "https://a.example/***");
+        assert_eq!(nodes[0]["pool"], "http");
+        assert_eq!(nodes[0]["circuit"], "closed");
+        assert_eq!(nodes[1]["pool"], "ws");
+        assert_eq!(nodes[1]["healthy"], false);
+        assert_eq!(nodes[1]["quarantine_reason"], "Genesis hash mismatch");
+        assert!(body["networks"].get("bsc").is_none());
+        assert_eq!(body["proxies"], Value::Array(Vec::new()));
+
+        let (status, body) = get_json(&app, "/status/networks/solana").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["nodes"].as_array().unwrap().len(), 2);
+
+        let (status, _) = get_json(&app, "/status/networks/unknown").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, _) = get_json(&app, "/metrics").await;
+        assert_eq!(status, StatusCode::OK);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/dashboard")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers()["content-type"]
+            .to_str()
+            .unwrap()
+            .starts_with("text/html"));
+    }
+}
@@ -1,60 +1,68 @@
-use crate::app::query::{fallback_handler, network_handler};
+use crate::app::access_log::log_access;
+use crate::app::auth::{require_api_key, ApiKeys};
+use crate::app::capture::capture_exchange;
+use crate::app::query::{
+    events_handler, fallback_handler, network_handler, network_path_handler, ws_network_handler,
+    EventStreamQuery,
+};
+use crate::app::rate_limit::{rate_limit, RateLimiter};
+use crate::app::request_id::assign_request_id;
 use crate::provider::Provider;
 use crate::provider::ProxyProvider;
+use crate::utils::config::Config;
 use axum::{
-    extract::ws::{Message, WebSocket, WebSocketUpgrade},
-    response::IntoResponse,
-    routing::get,
+    extract::{ws::WebSocketUpgrade, Query, Request},
+    http::HeaderMap,
+    middleware::{self, Next},
+    routing::{any, get, post},
     Router,
 };
 use std::sync::Arc;
-use tokio::sync::broadcast::Sender;
 
 pub fn get_router(
-    tx: Sender<String>,
+    config: &Config,
     provider: Arc<Provider>,
     proxy_provider: Arc<ProxyProvider>,
+    api_keys: Arc<ApiKeys>,
+    rate_limiter: Arc<RateLimiter>,
 ) -> Router {
-    let router = Router::new().route(
-        "/ws",
-        get(move |ws: WebSocketUpgrade| ws_handler(ws, tx.clone())),
-    );
-
-    let router = generate_network_routes!(router, network_handler);
+    let events_token = config.events_token.clone();
+    let router = Router::new()
+        .route(
+            "/ws",
+            get(
+                move |ws: WebSocketUpgrade,
+                      Query(query): Query<EventStreamQuery>,
+                      headers: HeaderMap| {
+                    events_handler(ws, query, headers, events_token.clone())
+                },
+            ),
+        )
+        .route("/ws/:network", get(ws_network_handler))
+        .route("/rpc/:network", post(network_handler))
+        .route("/rpc/:network/*path", any(network_path_handler));
 
+    let forward_request_id = config.forward_request_id;
     router
         .fallback(fallback_handler)
+        .layer(middleware::from_fn(capture_exchange))
+        .layer(middleware::from_fn(move |req: Request, next: Next| {
+            rate_limit(rate_limiter.clone(), req, next)
+        }))
+        .layer(middleware::from_fn(move |req: Request, next: Next| {
+            require_api_key(api_keys.clone(), req, next)
+        }))
+        .layer(middleware::from_fn(log_access))
+        .layer(middleware::from_fn(move |req: Request, next: Next| {
+            assign_request_id(forward_request_id, req, next)
+        }))
         .with_state((provider, proxy_provider))
 }
 
-async fn ws_handler(ws: WebSocketUpgrade, tx: Sender<String>) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_socket(socket, tx))
-}
-
-async fn handle_socket(mut socket: WebSocket, tx: Sender<String>) {
-    let mut rx = tx.subscribe();
-
-    loop {
-        tokio::select! {
-            // Receive messages from the WebSocket
-            Some(Ok(msg)) = socket.recv() => {
-                if let Message::Text(text) = msg {
-                    tx.send(text).unwrap();
-                }
-            }
-            // Send messages to the WebSocket
-            Ok(msg) = rx.recv() => {
-                if socket.send(Message::Text(msg)).await.is_err() {
-                    break;
-                }
-            }
-        }
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::app::events::{self, Event};
     use crate::utils::config::Config;
     use axum::{
         body::Body,
@@ -62,7 +70,7 @@ mod tests {
     };
     use futures_util::{SinkExt, StreamExt};
     use http_body_util::BodyExt;
-    use tokio::sync::broadcast;
+    use std::collections::HashMap;
     use tokio::time::Duration;
     use tokio::{net::TcpListener, time::timeout};
     use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
@@ -70,13 +78,25 @@ mod tests {
 
     #[tokio::test]
     async fn test_get_router() {
-        let (tx, _) = broadcast::channel(100);
         let config = Config::load().expect("Failed to load config");
-        let provider =
-            Arc::new(Provider::new(config.node_list_path).expect("Failed to initialize provider"));
-        let proxy_provider =
-            Arc::new(ProxyProvider::new(config.proxy_list_path, config.proxy_is_enabled).unwrap());
-        let app = get_router(tx, provider, proxy_provider);
+        let provider = Arc::new(
+            Provider::new(config.node_list_path.clone()).expect("Failed to initialize provider"),
+        );
+        let proxy_provider = Arc::new(
+            ProxyProvider::new(
+                config.proxy_list_path.clone(),
+                config.proxy_is_enabled,
+                Duration::from_secs(config.proxy_cooldown_secs),
+            )
+            .unwrap(),
+        );
+        let app = get_router(
+            &config,
+            provider,
+            proxy_provider,
+            Arc::default(),
+            Arc::default(),
+        );
 
         let response = app
             .oneshot(
@@ -95,69 +115,226 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_websocket_connection() {
-        let config = Config::load().expect("Failed to load config");
-        let (tx, _rx) = tokio::sync::broadcast::channel(100);
+    async fn test_event_stream() {
+        let mut config = Config::load().expect("Failed to load config");
+        config.events_token = Some("secret".to_string());
 
-        let provider =
-            Arc::new(Provider::new(config.node_list_path).expect("Failed to initialize provider"));
+        let provider = Arc::new(Provider::from_json(
+            serde_json::json!({"solana": ["http://127.0.0.1:1"]}),
+        ));
         let proxy_provider =
-            Arc::new(ProxyProvider::new(config.proxy_list_path, config.proxy_is_enabled).unwrap());
-        let app = get_router(tx, provider, proxy_provider);
+            Arc::new(ProxyProvider::new(String::new(), false, Duration::from_secs(1)).unwrap());
+        let app = get_router(
+            &config,
+            provider,
+            proxy_provider,
+            Arc::default(),
+            Arc::default(),
+        );
 
-        let listener = TcpListener::bind(config.http_server_address).await.unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
         let addr = listener.local_addr().unwrap();
 
         tokio::spawn(async move {
             axum::serve(listener, app).await.unwrap();
         });
 
-        let url = format!("ws://{}/ws", addr);
+        // Subscribers without the token or asking for unknown event types are rejected
+        assert!(connect_async(format!("ws://{}/ws", addr)).await.is_err());
+        let url = format!("ws://{}/ws?token=secret&types=retry,retries", addr);
+        assert!(connect_async(url).await.is_err());
+
+        let url = format!("ws://{}/ws?token=secret&types=retry&networks=bsc", addr);
         let (ws_stream, _) = connect_async(url)
             .await
             .expect("Failed to connect to websocket");
         let (mut write, mut read) = ws_stream.split();
 
-        let test_message = "Hello, WebSocket!";
-        write
-            .send(Message::Text(test_message.to_string()))
-            .await
-            .expect("Failed to send message");
-
-        match timeout(Duration::from_secs(5), read.next()).await {
-            Ok(Some(Ok(msg))) => {
-                assert_eq!(
-                    msg,
-                    Message::Text(test_message.to_string()),
-                    "Unexpected response from server"
-                );
-                println!(
-                    "Test passed: Server echoed the message correctly through broadcast system"
-                );
+        // Clients cannot inject anything into the stream; invalid filters are answered
+        for text in ["Hello, WebSocket!", r#"{"types": ["retries"]}"#] {
+            write
+                .send(Message::Text(text.to_string()))
+                .await
+                .expect("Failed to send message");
+            match timeout(Duration::from_secs(5), read.next()).await {
+                Ok(Some(Ok(Message::Text(text)))) => {
+                    let reply: serde_json::Value = serde_json::from_str(&text).unwrap();
+                    assert!(reply["error"]
+                        .as_str()
+                        .unwrap()
+                        .starts_with("Invalid filter"));
+                }
+                other => panic!("Unexpected event stream message: {:?}", other),
             }
-            Ok(Some(Err(e))) => panic!("Error receiving message: {:?}", e),
-            Ok(None) => panic!("WebSocket closed unexpectedly"),
-            Err(_) => panic!("Timeout waiting for server response"),
         }
 
-        let second_message = "Second test message";
-        write
-            .send(Message::Text(second_message.to_string()))
-            .await
-            .expect("Failed to send second message");
+        let retry = |network: &str| Event::Retry {
+            network: network.to_string(),
+            node: "http://127.0.0.1:1/".to_string(),
+            attempt: 1,
+            reason: "429 Too Many Requests".to_string(),
+        };
+        events::publish(retry("solana"));
+        events::publish(Event::ProxyQuarantine {
+            proxy: "10.0.0.1".to_string(),
+            seconds: 1,
+        });
+        events::publish(retry("bsc"));
 
         match timeout(Duration::from_secs(5), read.next()).await {
-            Ok(Some(Ok(msg))) => {
-                assert_eq!(
-                    msg,
-                    Message::Text(second_message.to_string()),
-                    "Unexpected response for second message"
-                );
-                println!("Test passed: Server correctly handled second message");
+            Ok(Some(Ok(Message::Text(text)))) => {
+                let event: serde_json::Value = serde_json::from_str(&text).unwrap();
+                assert_eq!(event["type"], "retry");
+                assert_eq!(event["network"], "bsc");
             }
-            Ok(Some(Err(e))) => panic!("Error receiving second message: {:?}", e),
-            Ok(None) => panic!("WebSocket closed unexpectedly after second message"),
-            Err(_) => panic!("Timeout waiting for server response to second message"),
+            other => panic!("Unexpected event stream message: {:?}", other),
+        }
+    }
+
+    type Fields = HashMap<String, String>;
+    type EventRecord = (Option<u64>, Fields);
+
+    /// Spans and events created while it is the default subscriber: spans by
+    /// ID, events with the ID of their parent span.
+    #[derive(Clone, Default)]
+    struct TraceRecorder {
+        spans: Arc<std::sync::Mutex<Vec<(String, Fields)>>>,
+        events: Arc<std::sync::Mutex<Vec<EventRecord>>>,
+    }
+
+    impl TraceRecorder {
+        fn spans(&self, name: &str) -> Vec<(u64, Fields)> {
+            let spans = self.spans.lock().unwrap();
+            let spans = spans.iter().zip(1..).filter(|((span, _), _)| span == name);
+            spans
+                .map(|((_, fields), id)| (id, fields.clone()))
+                .collect()
+        }
+
+        fn events(&self, parent: u64) -> Vec<Fields> {
+            let events = self.events.lock().unwrap();
+            let events = events.iter().filter(|(span, _)| *span == Some(parent));
+            events.map(|(_, fields)| fields.clone()).collect()
         }
     }
+
+    struct FieldVisitor<'a>(&'a mut Fields);
+
+    impl tracing::field::Visit for FieldVisitor<'_> {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.0
+                .insert(field.name().to_string(), format!("{:?}", value));
+        }
+    }
+
+    impl tracing::Subscriber for TraceRecorder {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, attrs: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            let mut fields = HashMap::new();
+            attrs.record(&mut FieldVisitor(&mut fields));
+            let mut spans = self.spans.lock().unwrap();
+            spans.push((attrs.metadata().name().to_string(), fields));
+            tracing::span::Id::from_u64(spans.len() as u64)
+        }
+
+        fn record(&self, span: &tracing::span::Id, values: &tracing::span::Record<'_>) {
+            let mut spans = self.spans.lock().unwrap();
+            let (_, fields) = &mut spans[span.into_u64() as usize - 1];
+            values.record(&mut FieldVisitor(fields));
+        }
+
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+        fn event(&self, event: &tracing::Event<'_>) {
+            let mut fields = HashMap::new();
+            event.record(&mut FieldVisitor(&mut fields));
+            let parent = event.parent().map(tracing::span::Id::into_u64);
+            self.events.lock().unwrap().push((parent, fields));
+        }
+
+        fn enter(&self, _: &tracing::span::Id) {}
+
+        fn exit(&self, _: &tracing::span::Id) {}
+    }
+
+    #[tokio::test]
+    async fn test_request_id() {
+        use wiremock::matchers::header;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let recorder = TraceRecorder::default();
+        let _guard = tracing::subscriber::set_default(recorder.clone());
+
+        let node = MockServer::start().await;
+        Mock::given(header("x-request-id", "client-42"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+            .expect(1)
+            .mount(&node)
+            .await;
+
+        let mut config = Config::load().expect("Failed to load config");
+        config.forward_request_id = true;
+        let provider = Arc::new(Provider::from_json(
+            serde_json::json!({"ethereum": [node.uri()]}),
+        ));
+        let proxy_provider =
+            Arc::new(ProxyProvider::new(String::new(), false, Duration::from_secs(1)).unwrap());
+        let app = get_router(
+            &config,
+            provider,
+            proxy_provider,
+            Arc::default(),
+            Arc::default(),
+        );
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/rpc/ethereum")
+                    .header("x-request-id", "client-42")
+                    .body(Body::from(
+                        r#"{"jsonrpc":"2.0","id":1,"method":"eth_chainId"}"#,
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-request-id"], "client-42");
+
+        // Each upstream attempt is traced in a span carrying the request ID,
+        // its outcome in an event of that span
+        let requests = recorder.spans("request");
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].1["request_id"], "client-42");
+        let attempts = recorder.spans("attempt");
+        assert_eq!(attempts.len(), 1);
+        let (attempt, fields) = &attempts[0];
+        assert_eq!(fields["request_id"], "client-42");
+        assert_eq!(fields["network"], "ethereum");
+        assert_eq!(fields["attempt"], "1");
+        assert_eq!(fields["node"], format!("{}/", node.uri()));
+        assert_eq!(fields["proxy"], "direct");
+        let outcomes = recorder.events(*attempt);
+        assert_eq!(outcomes.len(), 1);
+        assert_eq!(outcomes[0]["status"], "200");
+        assert!(outcomes[0].contains_key("duration_ms"));
+        assert!(!outcomes[0].contains_key("request_id"));
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/unknown")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.headers()["x-request-id"].len(), 32);
+    }
 }
@@ -1 +1,2 @@
+pub mod adminapi;
 pub mod httpapi;
@@ -1,5 +1,10 @@
+pub mod network;
+#[allow(clippy::module_inception)]
 pub mod provider;
 pub mod proxy;
+pub mod stats;
 
+pub use network::*;
 pub use provider::*;
 pub use proxy::*;
+pub use stats::{Latency, NodeStatus, Verification};
@@ -0,0 +1,442 @@
+use crate::app::networks::evm::Evm;
+use crate::app::networks::solana::Solana;
+use crate::app::pubsub::{PubSubProtocol, Session};
+use crate::app::rate_limit::SocketLimit;
+use crate::provider::proxy::Proxy;
+use crate::provider::{Provider, ProxyProvider};
+use crate::utils::error::ProviderError;
+use axum::extract::ws::WebSocket;
+use axum::response::Response;
+use axum::{body::Body, extract::Request};
+use log::{debug, info};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use strum_macros::{Display, EnumString};
+
+/// Protocol family a network speaks, deciding how its requests and
+/// subscriptions are handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize, EnumString, Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum ProtocolFamily {
+    Solana,
+    Evm,
+    JsonRpc,
+}
+
+/// Settings of Solana-like networks.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct SolanaSettings {
+    /// Genesis hash every node of the cluster must report.
+    pub genesis_hash: Option<String>,
+    /// Oldest `solana-core` version a node may run, e.g. `1.18.0`.
+    pub min_version: Option<String>,
+    /// Re-send accepted `sendTransaction` calls at this interval until they
+    /// are confirmed or expire; unset disables rebroadcasting.
+    pub rebroadcast_interval_ms: Option<u64>,
+    /// Percentiles `getPriorityFeeEstimate` returns when the request names none.
+    pub priority_fee_percentiles: Option<Vec<u8>>,
+    /// How long aggregated prioritization fees are reused.
+    pub priority_fee_cache_ms: Option<u64>,
+    /// Limit on a `getProgramAccounts` call including its response stream.
+    pub program_accounts_timeout_secs: Option<u64>,
+    /// `getProgramAccounts` calls in flight at once; more wait for a slot.
+    pub program_accounts_concurrency: Option<usize>,
+    /// How long identical `getProgramAccounts` results are reused; unset disables caching.
+    pub program_accounts_cache_secs: Option<u64>,
+    /// Responses larger than this are streamed but not cached.
+    pub program_accounts_cache_max_bytes: Option<usize>,
+    /// Size of all cached `getProgramAccounts` results of the network; the
+    /// least recently used are evicted to stay within it.
+    pub program_accounts_cache_total_bytes: Option<usize>,
+}
+
+impl SolanaSettings {
+    /// These settings with the ones set in `other` taking precedence.
+    fn merge(&self, other: &Self) -> Self {
+        Self {
+            genesis_hash: other.genesis_hash.clone().or(self.genesis_hash.clone()),
+            min_version: other.min_version.clone().or(self.min_version.clone()),
+            rebroadcast_interval_ms: other
+                .rebroadcast_interval_ms
+                .or(self.rebroadcast_interval_ms),
+            priority_fee_percentiles: other
+                .priority_fee_percentiles
+                .clone()
+                .or(self.priority_fee_percentiles.clone()),
+            priority_fee_cache_ms: other.priority_fee_cache_ms.or(self.priority_fee_cache_ms),
+            program_accounts_timeout_secs: other
+                .program_accounts_timeout_secs
+                .or(self.program_accounts_timeout_secs),
+            program_accounts_concurrency: other
+                .program_accounts_concurrency
+                .or(self.program_accounts_concurrency),
+            program_accounts_cache_secs: other
+                .program_accounts_cache_secs
+                .or(self.program_accounts_cache_secs),
+            program_accounts_cache_max_bytes: other
+                .program_accounts_cache_max_bytes
+                .or(self.program_accounts_cache_max_bytes),
+            program_accounts_cache_total_bytes: other
+                .program_accounts_cache_total_bytes
+                .or(self.program_accounts_cache_total_bytes),
+        }
+    }
+}
+
+/// Settings of EVM-like networks.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct EvmSettings {
+    /// Chain id every node of the network must report.
+    pub chain_id: Option<u64>,
+    /// Widest block range of an `eth_getLogs` call before it is split; 0 disables splitting.
+    pub get_logs_max_range: Option<u64>,
+}
+
+impl EvmSettings {
+    /// These settings with the ones set in `other` taking precedence.
+    fn merge(&self, other: &Self) -> Self {
+        Self {
+            chain_id: other.chain_id.or(self.chain_id),
+            get_logs_max_range: other.get_logs_max_range.or(self.get_logs_max_range),
+        }
+    }
+}
+
+/// Family-specific settings of a configured network.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "family", rename_all = "snake_case")]
+pub enum NetworkKind {
+    Solana(SolanaSettings),
+    Evm(EvmSettings),
+    JsonRpc,
+}
+
+impl NetworkKind {
+    pub fn family(&self) -> ProtocolFamily {
+        match self {
+            NetworkKind::Solana(_) => ProtocolFamily::Solana,
+            NetworkKind::Evm(_) => ProtocolFamily::Evm,
+            NetworkKind::JsonRpc => ProtocolFamily::JsonRpc,
+        }
+    }
+
+    /// `other` laid over these settings: settings of the same family are
+    /// merged field by field, another family replaces them.
+    fn overlay(&self, other: &Self) -> Self {
+        match (self, other) {
+            (NetworkKind::Solana(base), NetworkKind::Solana(settings)) => {
+                NetworkKind::Solana(base.merge(settings))
+            }
+            (NetworkKind::Evm(base), NetworkKind::Evm(settings)) => {
+                NetworkKind::Evm(base.merge(settings))
+            }
+            _ => other.clone(),
+        }
+    }
+}
+
+/// A network entry of the `networks` configuration list.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct NetworkConfig {
+    pub name: String,
+    #[serde(flatten)]
+    pub kind: NetworkKind,
+}
+
+impl NetworkConfig {
+    fn new(name: &str, kind: NetworkKind) -> Self {
+        Self {
+            name: name.to_string(),
+            kind,
+        }
+    }
+
+    fn solana(name: &str, genesis_hash: &str) -> Self {
+        Self::new(
+            name,
+            NetworkKind::Solana(SolanaSettings {
+                genesis_hash: Some(genesis_hash.to_string()),
+                ..Default::default()
+            }),
+        )
+    }
+
+    fn evm(name: &str, chain_id: u64) -> Self {
+        Self::new(
+            name,
+            NetworkKind::Evm(EvmSettings {
+                chain_id: Some(chain_id),
+                ..Default::default()
+            }),
+        )
+    }
+
+    /// Networks available without any configuration.
+    pub fn builtin() -> Vec<Self> {
+        vec![
+            Self::solana("solana", "5eykt4UsFv8P8NJdTREpY1vzqKqZKvdpKuc147dw2N9d"),
+            Self::solana(
+                "solana-devnet",
+                "EtWTRABZaYq6iMfeYKouRu166VU2xqa1wcaWoxPkrZBG",
+            ),
+            Self::evm("ethereum", 1),
+            Self::evm("bsc", 56),
+            Self::evm("bsc-testnet", 97),
+        ]
+    }
+}
+
+static NAMES: Lazy<Mutex<HashSet<&'static str>>> = Lazy::new(|| {
+    Mutex::new(HashSet::from([
+        Network::SOLANA.0,
+        Network::SOLANA_DEVNET.0,
+        Network::ETHEREUM.0,
+        Network::BSC.0,
+        Network::BSC_TESTNET.0,
+    ]))
+});
+
+static REGISTRY: Lazy<RwLock<BTreeMap<Network, NetworkKind>>> = Lazy::new(|| {
+    RwLock::new(
+        NetworkConfig::builtin()
+            .into_iter()
+            .map(|config| (Network::intern(&config.name), config.kind))
+            .collect(),
+    )
+});
+
+/// Handle of a registered network. Cheap to copy; the name is interned so it
+/// stays valid across registry reloads.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Network(&'static str);
+
+impl Network {
+    pub const SOLANA: Network = Network("solana");
+    pub const SOLANA_DEVNET: Network = Network("solana-devnet");
+    pub const ETHEREUM: Network = Network("ethereum");
+    pub const BSC: Network = Network("bsc");
+    pub const BSC_TESTNET: Network = Network("bsc-testnet");
+
+    fn intern(name: &str) -> Network {
+        let mut names = NAMES.lock().unwrap();
+        match names.get(name) {
+            Some(name) => Network(name),
+            None => {
+                let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+                names.insert(name);
+                Network(name)
+            }
+        }
+    }
+
+    /// Replaces the registered networks with the built-in defaults overlaid by
+    /// `configs`; an entry named like a built-in overrides the settings it sets.
+    pub fn load(configs: &[NetworkConfig]) -> Result<(), ProviderError> {
+        let networks = Self::registry(configs)?;
+        info!("Registered {} networks", networks.len());
+        debug!("Networks: {:?}", networks);
+        *REGISTRY.write().unwrap() = networks;
+        Ok(())
+    }
+
+    fn registry(
+        configs: &[NetworkConfig],
+    ) -> Result<BTreeMap<Network, NetworkKind>, ProviderError> {
+        let mut networks = BTreeMap::new();
+        for config in NetworkConfig::builtin().iter().chain(configs) {
+            let valid = !config.name.is_empty()
+                && config
+                    .name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if !valid {
+                return Err(ProviderError::ParseNetworkNameError);
+            }
+            let network = Network::intern(&config.name);
+            let kind = match networks.get(&network) {
+                Some(kind) => NetworkKind::overlay(kind, &config.kind),
+                None => config.kind.clone(),
+            };
+            networks.insert(network, kind);
+        }
+        Ok(networks)
+    }
+
+    /// All currently registered networks.
+    pub fn all() -> Vec<Network> {
+        REGISTRY.read().unwrap().keys().copied().collect()
+    }
+
+    /// Family settings of the network, or `None` once it is no longer registered.
+    pub fn kind(self) -> Option<NetworkKind> {
+        REGISTRY.read().unwrap().get(&self).cloned()
+    }
+
+    pub fn solana_settings(self) -> SolanaSettings {
+        match self.kind() {
+            Some(NetworkKind::Solana(settings)) => settings,
+            _ => SolanaSettings::default(),
+        }
+    }
+
+    pub fn evm_settings(self) -> EvmSettings {
+        match self.kind() {
+            Some(NetworkKind::Evm(settings)) => settings,
+            _ => EvmSettings::default(),
+        }
+    }
+
+    pub fn family(self) -> ProtocolFamily {
+        self.kind()
+            .map_or(ProtocolFamily::JsonRpc, |kind| kind.family())
+    }
+
+    pub async fn handle_request(
+        self,
+        provider: Arc<Provider>,
+        proxy_provider: Arc<ProxyProvider>,
+        req: Request<Body>,
+    ) -> Response {
+        match self.family() {
+            ProtocolFamily::Solana => {
+                Solana::handle_request(self, provider, proxy_provider, req).await
+            }
+            ProtocolFamily::Evm => Evm::handle_request(self, provider, proxy_provider, req).await,
+            ProtocolFamily::JsonRpc => {
+                Proxy::handle_request(self, provider, proxy_provider, req).await
+            }
+        }
+    }
+
+    pub async fn handle_socket(
+        self,
+        provider: Arc<Provider>,
+        proxy_provider: Arc<ProxyProvider>,
+        socket: WebSocket,
+        limit: Option<SocketLimit>,
+    ) {
+        match self.family() {
+            ProtocolFamily::Solana => {
+                Solana::handle_socket(self, provider, proxy_provider, socket, limit).await
+            }
+            ProtocolFamily::Evm => {
+                Evm::handle_socket(self, provider, proxy_provider, socket, limit).await
+            }
+            ProtocolFamily::JsonRpc => {
+                Session::run(
+                    PubSubProtocol::Ethereum,
+                    self,
+                    provider,
+                    proxy_provider,
+                    socket,
+                    limit,
+                )
+                .await
+            }
+        }
+    }
+}
+
+impl FromStr for Network {
+    type Err = ProviderError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        REGISTRY
+            .read()
+            .unwrap()
+            .keys()
+            .find(|network| network.0 == name)
+            .copied()
+            .ok_or(ProviderError::ParseNetworkNameError)
+    }
+}
+
+impl AsRef<str> for Network {
+    fn as_ref(&self) -> &str {
+        self.0
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+impl fmt::Debug for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_network_registry() {
+        let config: Vec<NetworkConfig> = serde_json::from_str(
+            r#"[
+                {"name": "polygon", "family": "evm", "chain_id": 137},
+                {"name": "bsc-testnet", "family": "evm", "get_logs_max_range": 500},
+                {"name": "solana-devnet", "family": "solana", "min_version": "1.18.0"},
+                {"name": "fuel", "family": "json_rpc"}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config[0].kind,
+            NetworkKind::Evm(EvmSettings {
+                chain_id: Some(137),
+                ..Default::default()
+            })
+        );
+
+        // Built on a local registry; the global one is shared with concurrent tests
+        let networks = Network::registry(&config).unwrap();
+        let polygon = Network::intern("polygon");
+        assert_eq!(polygon.to_string(), "polygon");
+        assert_eq!(networks[&polygon].family(), ProtocolFamily::Evm);
+        assert_eq!(networks[&Network::intern("fuel")], NetworkKind::JsonRpc);
+        // Overrides of a built-in keep the settings they leave unset
+        assert_eq!(
+            networks[&Network::BSC_TESTNET],
+            NetworkKind::Evm(EvmSettings {
+                chain_id: Some(97),
+                get_logs_max_range: Some(500),
+            })
+        );
+        assert_eq!(
+            networks[&Network::SOLANA_DEVNET],
+            NetworkKind::Solana(SolanaSettings {
+                genesis_hash: Some("EtWTRABZaYq6iMfeYKouRu166VU2xqa1wcaWoxPkrZBG".to_string()),
+                min_version: Some("1.18.0".to_string()),
+                ..Default::default()
+            })
+        );
+        assert_eq!(networks[&Network::SOLANA].family(), ProtocolFamily::Solana);
+
+        let invalid = [NetworkConfig::new("a/b", NetworkKind::JsonRpc)];
+        assert!(Network::registry(&invalid).is_err());
+
+        // Without the entries only the built-ins remain
+        let networks = Network::registry(&[]).unwrap();
+        assert!(!networks.contains_key(&polygon));
+        assert_eq!(networks.len(), 5);
+        assert_eq!(
+            networks[&Network::BSC_TESTNET],
+            NetworkKind::Evm(EvmSettings {
+                chain_id: Some(97),
+                ..Default::default()
+            })
+        );
+        assert_eq!(Network::from_str("solana").unwrap(), Network::SOLANA);
+    }
+}
@@ -1,9 +1,8 @@
-use crate::app::networks::solana::Solana;
-use crate::provider::proxy::Proxy;
-use crate::provider::ProxyProvider;
+use crate::provider::stats::{NodeStats, NodeStatus, Verification};
+use crate::provider::Network;
 use crate::utils::error::ProviderError;
-use axum::response::Response;
-use axum::{body::Body, extract::Request};
+use crate::utils::redact::redact_url;
+use chrono::Utc;
 use log::debug;
 use serde_json::Value;
 use std::collections::HashMap;
@@ -11,42 +10,248 @@ use std::fs::File;
 use std::io::Read;
 use std::str::FromStr;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
-use strum::AsRefStr;
-use strum_macros::{Display, EnumIter, EnumString};
-
-#[derive(Debug, Hash, Eq, PartialEq, Clone, Copy, EnumString, Display, EnumIter, AsRefStr)]
-#[strum(serialize_all = "kebab-case")]
-pub enum Network {
-    Solana,
-    SolanaDevnet,
-    Ethereum,
-    BSC,
-    BSCTestnet,
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
+
+/// Per-network settings of the upstream WebSocket subscriptions.
+#[derive(Debug, Clone, Copy)]
+pub struct WsSettings {
+    /// Number of upstream connections every subscription is held on.
+    pub redundancy: usize,
+    /// Drop notifications already delivered by another redundant upstream.
+    pub deduplicate: bool,
 }
 
-impl Network {
-    pub async fn handle_request(
-        self,
-        provider: Arc<Provider>,
-        proxy_provider: Arc<ProxyProvider>,
-        req: Request<Body>,
-    ) -> Response {
-        match self {
-            Network::Solana => Solana::handle_request(self, provider, proxy_provider, req).await,
-            _ => Proxy::handle_request(self, provider, proxy_provider, req).await,
+impl Default for WsSettings {
+    fn default() -> Self {
+        Self {
+            redundancy: 1,
+            deduplicate: true,
         }
     }
 }
 
+/// Node list a request is routed to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NodePool {
+    #[default]
+    Default,
+    /// Nodes reserved for heavy `getProgramAccounts` calls; the default list
+    /// when the network has none.
+    ProgramAccounts,
+}
+
+#[derive(Debug, Default)]
+struct NodeLists {
+    nodes: HashMap<Network, Vec<String>>,
+    indices: HashMap<Network, Arc<AtomicUsize>>,
+    program_accounts_nodes: HashMap<Network, Vec<String>>,
+    program_accounts_indices: HashMap<Network, Arc<AtomicUsize>>,
+    ws_nodes: HashMap<Network, Vec<String>>,
+    ws_indices: HashMap<Network, Arc<AtomicUsize>>,
+    ws_settings: HashMap<Network, WsSettings>,
+}
+
+impl NodeLists {
+    fn contains(&self, network: Network, url: &str) -> bool {
+        [&self.nodes, &self.program_accounts_nodes, &self.ws_nodes]
+            .iter()
+            .any(|lists| {
+                lists
+                    .get(&network)
+                    .is_some_and(|urls| urls.iter().any(|u| u == url))
+            })
+    }
+}
+
 #[derive(Debug)]
 pub struct Provider {
-    pub nodes: HashMap<Network, Vec<String>>,
-    pub indices: HashMap<Network, Arc<AtomicUsize>>,
+    path: String,
+    lists: RwLock<NodeLists>,
+    /// Nodes excluded from rotation, with the reason they were excluded.
+    quarantine: RwLock<HashMap<Network, HashMap<String, String>>>,
+    stats: Mutex<HashMap<Network, HashMap<String, NodeStats>>>,
+}
+
+/// An upstream attempt on a node, counted as in flight until dropped.
+pub struct NodeAttempt<'a> {
+    provider: &'a Provider,
+    network: Network,
+    url: String,
+    start: Instant,
+}
+
+impl NodeAttempt<'_> {
+    /// Records the attempt's latency and whether the node answered properly.
+    pub fn finish(self, ok: bool) {
+        let duration = self.start.elapsed();
+        self.provider
+            .with_stats(self.network, &self.url, |stats| stats.record(duration, ok));
+    }
+}
+
+impl Drop for NodeAttempt<'_> {
+    fn drop(&mut self) {
+        self.provider.with_stats(self.network, &self.url, |stats| {
+            stats.in_flight = stats.in_flight.saturating_sub(1)
+        });
+    }
 }
 
 impl Provider {
     pub fn new(path: String) -> Result<Self, ProviderError> {
+        let lists = Self::read_node_lists(&path)?;
+        Ok(Provider {
+            path,
+            lists: RwLock::new(lists),
+            quarantine: RwLock::new(HashMap::new()),
+            stats: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Provider over node lists given in the node list file's format, for tests.
+    #[cfg(test)]
+    pub(crate) fn from_json(json: Value) -> Self {
+        Provider {
+            path: String::new(),
+            lists: RwLock::new(Self::parse_node_lists(json).unwrap()),
+            quarantine: RwLock::new(HashMap::new()),
+            stats: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Re-reads the node list file, keeping the current lists if it is invalid.
+    pub fn reload(&self) -> Result<(), ProviderError> {
+        let lists = Self::read_node_lists(&self.path)?;
+        self.quarantine
+            .write()
+            .unwrap()
+            .iter_mut()
+            .for_each(|(network, urls)| urls.retain(|url, _| lists.contains(*network, url)));
+        self.stats
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .for_each(|(network, urls)| urls.retain(|url, _| lists.contains(*network, url)));
+        *self.lists.write().unwrap() = lists;
+        Ok(())
+    }
+
+    /// Excludes a node from rotation; returns whether it was serving before.
+    pub fn quarantine(&self, network: Network, url: &str, reason: String) -> bool {
+        let mut quarantine = self.quarantine.write().unwrap();
+        quarantine
+            .entry(network)
+            .or_default()
+            .insert(url.to_string(), reason)
+            .is_none()
+    }
+
+    /// Returns a node to rotation; returns whether it was quarantined.
+    pub fn release(&self, network: Network, url: &str) -> bool {
+        let mut quarantine = self.quarantine.write().unwrap();
+        quarantine
+            .get_mut(&network)
+            .is_some_and(|urls| urls.remove(url).is_some())
+    }
+
+    /// Quarantined nodes of the network with the reason of their exclusion.
+    pub fn quarantined(&self, network: Network) -> HashMap<String, String> {
+        let quarantine = self.quarantine.read().unwrap();
+        quarantine.get(&network).cloned().unwrap_or_default()
+    }
+
+    fn with_stats<T>(&self, network: Network, url: &str, f: impl FnOnce(&mut NodeStats) -> T) -> T {
+        let mut stats = self.stats.lock().unwrap();
+        let node = stats
+            .entry(network)
+            .or_default()
+            .entry(url.to_string())
+            .or_default();
+        f(node)
+    }
+
+    /// Starts tracking an upstream attempt on a node.
+    pub fn start_attempt(&self, network: Network, url: &str) -> NodeAttempt<'_> {
+        self.with_stats(network, url, |stats| stats.in_flight += 1);
+        NodeAttempt {
+            provider: self,
+            network,
+            url: url.to_string(),
+            start: Instant::now(),
+        }
+    }
+
+    /// Records the outcome of a node's chain identity check.
+    pub fn record_verification(&self, network: Network, url: &str, outcome: String) {
+        let verification = Verification {
+            at: Utc::now(),
+            outcome,
+        };
+        self.with_stats(network, url, |stats| {
+            stats.verification = Some(verification)
+        });
+    }
+
+    /// Records the latest block or slot a node reported.
+    pub fn record_head(&self, network: Network, url: &str, head: u64) {
+        self.with_stats(network, url, |stats| stats.head = Some(head));
+    }
+
+    /// Health and recent activity of every configured node of the network.
+    pub fn status(&self, network: Network) -> Vec<NodeStatus> {
+        let pools = [
+            ("http", self.node_urls(network)),
+            ("program_accounts", self.program_accounts_node_urls(network)),
+            ("ws", self.ws_node_urls(network)),
+        ];
+        let quarantined = self.quarantined(network);
+        let stats = self.stats.lock().unwrap();
+        let network_stats = stats.get(&network);
+        let highest =
+            network_stats.and_then(|nodes| nodes.values().filter_map(|node| node.head).max());
+
+        pools
+            .into_iter()
+            .flat_map(|(pool, urls)| urls.into_iter().map(move |url| (pool, url)))
+            .map(|(pool, url)| {
+                let node = network_stats.and_then(|nodes| nodes.get(&url));
+                let quarantine_reason = quarantined.get(&url).cloned();
+                let head = node.and_then(|node| node.head);
+                NodeStatus {
+                    node: redact_url(&url),
+                    pool,
+                    healthy: quarantine_reason.is_none(),
+                    circuit: if quarantine_reason.is_some() {
+                        "open"
+                    } else {
+                        "closed"
+                    },
+                    quarantine_reason,
+                    verification: node.and_then(|node| node.verification.clone()),
+                    head,
+                    lag: head.zip(highest).map(|(head, highest)| highest - head),
+                    latency_ms: node.and_then(NodeStats::latency),
+                    error_rate: node.and_then(NodeStats::error_rate),
+                    attempts: node.map_or(0, NodeStats::attempts),
+                    total_attempts: node.map_or(0, |node| node.total),
+                    in_flight: node.map_or(0, |node| node.in_flight),
+                }
+            })
+            .collect()
+    }
+
+    /// Picks the next node of `urls` in rotation that isn't quarantined.
+    fn next_url(&self, network: Network, urls: &[String], index: &AtomicUsize) -> Option<String> {
+        let quarantine = self.quarantine.read().unwrap();
+        let excluded = quarantine.get(&network);
+        (0..urls.len())
+            .map(|_| &urls[index.fetch_add(1, Ordering::SeqCst) % urls.len()])
+            .find(|url| !excluded.is_some_and(|excluded| excluded.contains_key(*url)))
+            .cloned()
+    }
+
+    fn read_node_lists(path: &str) -> Result<NodeLists, ProviderError> {
         let mut file = match File::open(path) {
             Ok(file) => file,
             Err(e) => return Err(ProviderError::ReadNodeListError(e)),
@@ -63,23 +268,64 @@ impl Provider {
             Err(e) => return Err(ProviderError::ParseNodeListError(e.into())),
         };
 
+        Self::parse_node_lists(json)
+    }
+
+    fn parse_node_lists(json: Value) -> Result<NodeLists, ProviderError> {
         let mut nodes = HashMap::new();
         let mut indices = HashMap::new();
+        let mut program_accounts_nodes = HashMap::new();
+        let mut program_accounts_indices = HashMap::new();
+        let mut ws_nodes = HashMap::new();
+        let mut ws_indices = HashMap::new();
+        let mut ws_settings = HashMap::new();
 
         if let Value::Object(networks) = json {
             for (network_str, urls) in networks {
                 match Network::from_str(&network_str) {
                     Ok(network) => {
-                        if let Value::Array(url_list) = urls {
-                            let urls: Vec<String> = url_list
-                                .into_iter()
-                                .filter_map(|v| v.as_str().map(String::from))
-                                .collect();
-
-                            if !urls.is_empty() {
-                                nodes.insert(network, urls);
-                                indices.insert(network, Arc::new(AtomicUsize::new(0)));
+                        // A network is either a plain list of HTTP URLs or an object
+                        // with separate "http", "program_accounts" and "ws" lists and
+                        // WebSocket settings.
+                        let (http_urls, program_accounts_urls, ws_urls) = match urls {
+                            Value::Object(mut lists) => {
+                                let defaults = WsSettings::default();
+                                let settings = WsSettings {
+                                    redundancy: lists
+                                        .get("ws_redundancy")
+                                        .and_then(Value::as_u64)
+                                        .map_or(defaults.redundancy, |n| n.max(1) as usize),
+                                    deduplicate: lists
+                                        .get("ws_deduplicate")
+                                        .and_then(Value::as_bool)
+                                        .unwrap_or(defaults.deduplicate),
+                                };
+                                ws_settings.insert(network, settings);
+                                (
+                                    lists.remove("http").unwrap_or_default(),
+                                    lists.remove("program_accounts").unwrap_or_default(),
+                                    lists.remove("ws").unwrap_or_default(),
+                                )
                             }
+                            urls => (urls, Value::Null, Value::Null),
+                        };
+
+                        let urls = Self::parse_url_list(http_urls);
+                        if !urls.is_empty() {
+                            nodes.insert(network, urls);
+                            indices.insert(network, Arc::new(AtomicUsize::new(0)));
+                        }
+
+                        let urls = Self::parse_url_list(program_accounts_urls);
+                        if !urls.is_empty() {
+                            program_accounts_nodes.insert(network, urls);
+                            program_accounts_indices.insert(network, Arc::new(AtomicUsize::new(0)));
+                        }
+
+                        let urls = Self::parse_url_list(ws_urls);
+                        if !urls.is_empty() {
+                            ws_nodes.insert(network, urls);
+                            ws_indices.insert(network, Arc::new(AtomicUsize::new(0)));
                         }
                     }
                     Err(_) => return Err(ProviderError::ParseNetworkNameError),
@@ -89,15 +335,93 @@ impl Provider {
 
         debug!("Provider initialized with {} nodes", nodes.len());
         debug!("Nodes: {:?}", nodes);
+        debug!("WebSocket nodes: {:?}", ws_nodes);
+
+        Ok(NodeLists {
+            nodes,
+            indices,
+            program_accounts_nodes,
+            program_accounts_indices,
+            ws_nodes,
+            ws_indices,
+            ws_settings,
+        })
+    }
+
+    fn parse_url_list(urls: Value) -> Vec<String> {
+        match urls {
+            Value::Array(url_list) => url_list
+                .into_iter()
+                .filter_map(|v| v.as_str().map(String::from))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// HTTP nodes currently configured for the network.
+    pub fn node_urls(&self, network: Network) -> Vec<String> {
+        let lists = self.lists.read().unwrap();
+        lists.nodes.get(&network).cloned().unwrap_or_default()
+    }
+
+    /// Nodes reserved for `getProgramAccounts` calls of the network.
+    pub fn program_accounts_node_urls(&self, network: Network) -> Vec<String> {
+        let lists = self.lists.read().unwrap();
+        let urls = lists.program_accounts_nodes.get(&network);
+        urls.cloned().unwrap_or_default()
+    }
+
+    /// HTTP nodes of the network that aren't quarantined.
+    pub fn healthy_node_urls(&self, network: Network) -> Vec<String> {
+        let quarantined = self.quarantined(network);
+        self.node_urls(network)
+            .into_iter()
+            .filter(|url| !quarantined.contains_key(url))
+            .collect()
+    }
 
-        Ok(Provider { nodes, indices })
+    /// WebSocket nodes currently configured for the network.
+    pub fn ws_node_urls(&self, network: Network) -> Vec<String> {
+        let lists = self.lists.read().unwrap();
+        lists.ws_nodes.get(&network).cloned().unwrap_or_default()
     }
 
     pub async fn get_node_url(&self, network: Network) -> Option<String> {
-        if let Some(urls) = self.nodes.get(&network) {
-            let index = self.indices.get(&network).unwrap();
-            let current_index = index.fetch_add(1, Ordering::SeqCst) % urls.len();
-            Some(urls[current_index].clone())
+        let lists = self.lists.read().unwrap();
+        if let Some(urls) = lists.nodes.get(&network) {
+            let index = lists.indices.get(&network).unwrap();
+            self.next_url(network, urls, index)
+        } else {
+            None
+        }
+    }
+
+    pub async fn get_pool_node_url(&self, network: Network, pool: NodePool) -> Option<String> {
+        if pool == NodePool::ProgramAccounts {
+            let lists = self.lists.read().unwrap();
+            if let Some(urls) = lists.program_accounts_nodes.get(&network) {
+                let index = lists.program_accounts_indices.get(&network).unwrap();
+                return self.next_url(network, urls, index);
+            }
+        }
+        self.get_node_url(network).await
+    }
+
+    pub fn ws_node_count(&self, network: Network) -> usize {
+        let lists = self.lists.read().unwrap();
+        lists.ws_nodes.get(&network).map_or(0, Vec::len)
+    }
+
+    pub fn ws_settings(&self, network: Network) -> WsSettings {
+        let lists = self.lists.read().unwrap();
+        lists.ws_settings.get(&network).copied().unwrap_or_default()
+    }
+
+    pub async fn get_ws_node_url(&self, network: Network) -> Option<String> {
+        let lists = self.lists.read().unwrap();
+        if let Some(urls) = lists.ws_nodes.get(&network) {
+            let index = lists.ws_indices.get(&network).unwrap();
+            self.next_url(network, urls, index)
         } else {
             None
         }
@@ -112,19 +436,98 @@ mod tests {
     #[test]
     fn test_network_methods() {
         // Test from_str
-        assert_eq!(Network::from_str("solana").unwrap(), Network::Solana);
+        assert_eq!(Network::from_str("solana").unwrap(), Network::SOLANA);
         assert_eq!(
             Network::from_str("solana-devnet").unwrap(),
-            Network::SolanaDevnet
+            Network::SOLANA_DEVNET
         );
+        assert!(Network::from_str("unknown").is_err());
 
         // Test to_string
-        assert_eq!(Network::Solana.to_string(), "solana");
-        assert_eq!(Network::SolanaDevnet.to_string(), "solana-devnet");
-        assert_eq!(Network::BSCTestnet.to_string(), "bsc-testnet");
+        assert_eq!(Network::SOLANA.to_string(), "solana");
+        assert_eq!(Network::SOLANA_DEVNET.to_string(), "solana-devnet");
+        assert_eq!(Network::BSC_TESTNET.to_string(), "bsc-testnet");
 
         // Test as_ref
-        assert_eq!(Network::Ethereum.as_ref(), "ethereum");
+        assert_eq!(Network::ETHEREUM.as_ref(), "ethereum");
         assert_eq!(Network::BSC.as_ref(), "bsc");
     }
+
+    #[tokio::test]
+    async fn test_ws_node_list() {
+        let provider = Provider::from_json(serde_json::json!({
+            "solana": {
+                "http": ["https://a.example"],
+                "program_accounts": ["https://gpa.example"],
+                "ws": ["wss://a.example"]
+            },
+            "bsc": ["https://b.example"]
+        }));
+
+        assert_eq!(
+            provider.node_urls(Network::SOLANA),
+            vec!["https://a.example"]
+        );
+        assert_eq!(
+            provider.ws_node_urls(Network::SOLANA),
+            vec!["wss://a.example"]
+        );
+        assert_eq!(provider.node_urls(Network::BSC), vec!["https://b.example"]);
+        assert!(provider.ws_node_urls(Network::BSC).is_empty());
+        assert_eq!(
+            provider
+                .get_pool_node_url(Network::SOLANA, NodePool::ProgramAccounts)
+                .await,
+            Some("https://gpa.example".to_string())
+        );
+        assert_eq!(
+            provider
+                .get_pool_node_url(Network::BSC, NodePool::ProgramAccounts)
+                .await,
+            Some("https://b.example".to_string())
+        );
+
+        // Activity of nodes is reported under their redacted URLs
+        provider
+            .start_attempt(Network::BSC, "https://b.example")
+            .finish(false);
+        provider.record_head(Network::BSC, "https://b.example", 7);
+        let in_flight = provider.start_attempt(Network::BSC, "https://b.example");
+        let status = provider.status(Network::BSC);
+        assert_eq!(status.len(), 1);
+        assert_eq!(status[0].node, "https://b.example/");
+        assert_eq!(status[0].in_flight, 1);
+        assert_eq!(status[0].error_rate, Some(1.0));
+        assert_eq!(status[0].lag, Some(0));
+        in_flight.finish(true);
+        assert_eq!(provider.status(Network::BSC)[0].in_flight, 0);
+    }
+
+    #[test]
+    fn test_reload() {
+        let path =
+            std::env::temp_dir().join(format!("tutus_nodus_nodes_{}.json", std::process::id()));
+        let write = |json: &str| std::fs::write(&path, json).unwrap();
+
+        // The file is gone before anything is asserted
+        write(r#"{"solana": ["https://a.example"]}"#);
+        let provider = Provider::new(path.to_string_lossy().to_string()).ok();
+        write(r#"{"bsc": ["https://c.example"]}"#);
+        let reloaded = provider.as_ref().is_some_and(|p| p.reload().is_ok());
+        let lists = provider
+            .as_ref()
+            .map(|p| (p.node_urls(Network::SOLANA), p.node_urls(Network::BSC)));
+        write(r#"{"unknown": ["https://d.example"]}"#);
+        let rejected = provider.as_ref().is_some_and(|p| p.reload().is_err());
+        std::fs::remove_file(&path).unwrap();
+
+        // Reloading picks up the new list and keeps the old one on errors
+        assert!(reloaded);
+        assert_eq!(lists, Some((vec![], vec!["https://c.example".to_string()])));
+        assert!(rejected);
+        assert_eq!(
+            provider.unwrap().node_urls(Network::BSC),
+            vec!["https://c.example"]
+        );
+    }
 }
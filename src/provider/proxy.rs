@@ -1,44 +1,95 @@
-use crate::provider::{Network, Provider};
+use crate::app::access_log::AccessContext;
+use crate::app::events::{self, Event};
+use crate::app::metrics::{self, ErrorKind, InFlight};
+use crate::app::networks::solana::transaction::TransactionSummary;
+use crate::app::request_id::RequestId;
+use crate::provider::{Network, NodePool, Provider};
+use crate::utils::jsonrpc::rpc_methods;
+use crate::utils::redact::redact_url;
 use axum::body::Body;
 use axum::http::{HeaderMap, Method, Request, StatusCode};
 use axum::response::Response;
 use bytes::Bytes;
 use futures_util::StreamExt;
 use http_body_util::BodyExt;
-use log::{debug, error, info, warn};
-use reqwest::header::{HeaderValue, HOST};
+use reqwest::header::{HeaderValue, CONTENT_TYPE, HOST};
 use reqwest::{Client, Url};
+use serde::Serialize;
 use serde_json::Value;
 use std::collections::HashMap;
+use std::net::IpAddr;
+use std::str::FromStr;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use strum_macros::Display;
+use tracing::{debug, error, info, warn, Instrument};
 
 const MAX_RETRIES: usize = 5;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Where and how long a proxied request is sent upstream.
+#[derive(Debug, Clone)]
+pub struct Upstream {
+    pub pool: NodePool,
+    /// Limit on the whole exchange, including streaming the response body.
+    pub timeout: Duration,
+    /// Sub-path and query string appended to the node's base URL, as in
+    /// `eth/v1/node/health?verbose=1`.
+    pub path: Option<String>,
+}
+
+impl Default for Upstream {
+    fn default() -> Self {
+        Self {
+            pool: NodePool::Default,
+            timeout: REQUEST_TIMEOUT,
+            path: None,
+        }
+    }
+}
 
-#[derive(Debug, Hash, Eq, PartialEq, Clone, Copy)]
+#[derive(Debug, Hash, Eq, PartialEq, Clone, Copy, Display)]
+#[strum(serialize_all = "snake_case")]
 pub enum ProxyType {
     Disabled,
     Socks5,
     Random,
+    /// Not a proxy: a pool of local IPv4/IPv6 addresses outbound connections are bound to.
+    LocalAddress,
 }
 
-impl ProxyType {
-    pub fn from_str(s: &str) -> Result<Self, ProxyProviderError> {
+impl FromStr for ProxyType {
+    type Err = ProxyProviderError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s.to_lowercase().as_str() {
             "disabled" => Ok(ProxyType::Disabled),
             "socks5" => Ok(ProxyType::Socks5),
             "random" => Ok(ProxyType::Random),
+            "local_address" | "local-address" => Ok(ProxyType::LocalAddress),
             _ => Err(ProxyProviderError::InvalidProxyType),
         }
     }
 }
 
+/// State of one proxy or local address, as shown by the admin API.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProxyStatus {
+    #[serde(rename = "type")]
+    pub proxy_type: String,
+    pub proxy: String,
+    /// Seconds left of the cooldown, while it lasts.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cooldown_secs: Option<u64>,
+}
+
 #[derive(Debug)]
 pub struct ProxyProvider {
     proxies: HashMap<ProxyType, Vec<String>>,
     indices: HashMap<ProxyType, Arc<AtomicUsize>>,
+    cooldowns: Mutex<HashMap<String, Instant>>,
+    cooldown: Duration,
     pub is_enabled: bool,
 }
 
@@ -47,14 +98,21 @@ pub enum ProxyProviderError {
     ReadProxyListError(std::io::Error),
     ParseProxyListError(serde_json::Error),
     InvalidProxyType,
+    InvalidLocalAddress(String),
 }
 
 impl ProxyProvider {
-    pub fn new(path: String, is_enabled: bool) -> Result<Self, ProxyProviderError> {
+    pub fn new(
+        path: String,
+        is_enabled: bool,
+        cooldown: Duration,
+    ) -> Result<Self, ProxyProviderError> {
         if !is_enabled {
             return Ok(ProxyProvider {
                 proxies: HashMap::new(),
                 indices: HashMap::new(),
+                cooldowns: Mutex::new(HashMap::new()),
+                cooldown,
                 is_enabled,
             });
         }
@@ -65,6 +123,16 @@ impl ProxyProvider {
         let json: Value =
             serde_json::from_str(&contents).map_err(ProxyProviderError::ParseProxyListError)?;
 
+        Self::from_lists(json, cooldown)
+    }
+
+    /// Proxy provider over lists given in the proxy list file's format, for tests.
+    #[cfg(test)]
+    pub(crate) fn from_json(json: Value) -> Result<Self, ProxyProviderError> {
+        Self::from_lists(json, Duration::from_secs(60))
+    }
+
+    fn from_lists(json: Value, cooldown: Duration) -> Result<Self, ProxyProviderError> {
         let mut proxies = HashMap::new();
         let mut indices = HashMap::new();
 
@@ -77,6 +145,12 @@ impl ProxyProvider {
                         .filter_map(|v| v.as_str().map(String::from))
                         .collect();
 
+                    if proxy_type == ProxyType::LocalAddress {
+                        if let Some(invalid) = urls.iter().find(|a| a.parse::<IpAddr>().is_err()) {
+                            return Err(ProxyProviderError::InvalidLocalAddress(invalid.clone()));
+                        }
+                    }
+
                     if !urls.is_empty() {
                         proxies.insert(proxy_type, urls);
                         indices.insert(proxy_type, Arc::new(AtomicUsize::new(0)));
@@ -94,17 +168,31 @@ impl ProxyProvider {
         Ok(ProxyProvider {
             proxies,
             indices,
-            is_enabled,
+            cooldowns: Mutex::new(HashMap::new()),
+            cooldown,
+            is_enabled: true,
         })
     }
 
+    /// Returns the next entry of the given type in round-robin order, skipping entries
+    /// that are cooling down. If every entry is cooling down, the next one is returned anyway.
     pub fn get_proxy_url(&self, proxy_type: ProxyType) -> Option<String> {
         match proxy_type {
             ProxyType::Disabled => None,
             _ => {
                 if let Some(urls) = self.proxies.get(&proxy_type) {
                     let index = self.indices.get(&proxy_type).unwrap();
-                    let current_index = index.fetch_add(1, Ordering::SeqCst) % urls.len();
+                    let cooldowns = self.cooldowns.lock().unwrap();
+                    let now = Instant::now();
+                    let mut current_index = index.fetch_add(1, Ordering::SeqCst) % urls.len();
+                    for _ in 0..urls.len() {
+                        match cooldowns.get(&urls[current_index]) {
+                            Some(until) if *until > now => {
+                                current_index = index.fetch_add(1, Ordering::SeqCst) % urls.len();
+                            }
+                            _ => break,
+                        }
+                    }
                     Some(urls[current_index].clone())
                 } else {
                     None
@@ -112,11 +200,60 @@ impl ProxyProvider {
             }
         }
     }
+
+    pub fn get_local_address(&self) -> Option<IpAddr> {
+        self.get_proxy_url(ProxyType::LocalAddress)
+            .and_then(|address| address.parse().ok())
+    }
+
+    /// Every configured proxy and local address with its remaining cooldown.
+    pub fn status(&self) -> Vec<ProxyStatus> {
+        let cooldowns = self.cooldowns.lock().unwrap();
+        let now = Instant::now();
+        let mut status: Vec<ProxyStatus> = self
+            .proxies
+            .iter()
+            .flat_map(|(proxy_type, entries)| {
+                entries.iter().map(|entry| ProxyStatus {
+                    proxy_type: proxy_type.to_string(),
+                    proxy: Self::label(entry),
+                    cooldown_secs: cooldowns
+                        .get(entry)
+                        .filter(|until| **until > now)
+                        .map(|until| (*until - now).as_secs_f64().ceil() as u64),
+                })
+            })
+            .collect();
+        status.sort_by(|a, b| (&a.proxy_type, &a.proxy).cmp(&(&b.proxy_type, &b.proxy)));
+        status
+    }
+
+    /// Proxy URLs are redacted; local addresses are our own and need no redaction.
+    fn label(entry: &str) -> String {
+        match entry.parse::<IpAddr>() {
+            Ok(_) => entry.to_string(),
+            Err(_) => redact_url(entry),
+        }
+    }
+
+    /// Takes a proxy URL or local address out of rotation for the configured cooldown.
+    pub fn cool_down(&self, entry: &str) {
+        debug!("Cooling down {} for {:?}", entry, self.cooldown);
+        events::publish(Event::ProxyQuarantine {
+            proxy: Self::label(entry),
+            seconds: self.cooldown.as_secs(),
+        });
+        self.cooldowns
+            .lock()
+            .unwrap()
+            .insert(entry.to_string(), Instant::now() + self.cooldown);
+    }
 }
 
 pub struct Proxy {
     pub proxy_provider: Arc<ProxyProvider>,
     current_proxy_url: Option<String>,
+    current_local_address: Option<IpAddr>,
 }
 
 impl Proxy {
@@ -124,7 +261,22 @@ impl Proxy {
         Self {
             proxy_provider,
             current_proxy_url: None,
+            current_local_address: None,
+        }
+    }
+
+    fn cool_down(&mut self) {
+        if let Some(proxy_url) = self.current_proxy_url.take() {
+            self.proxy_provider.cool_down(&proxy_url);
         }
+        if let Some(local_address) = self.current_local_address.take() {
+            self.proxy_provider.cool_down(&local_address.to_string());
+        }
+    }
+
+    fn rotate(&mut self) {
+        self.current_proxy_url = None;
+        self.current_local_address = None;
     }
 
     pub async fn handle_request(
@@ -132,6 +284,18 @@ impl Proxy {
         provider: Arc<Provider>,
         proxy_provider: Arc<ProxyProvider>,
         req: Request<Body>,
+    ) -> Response {
+        Self::forward(network, provider, proxy_provider, req, Upstream::default()).await
+    }
+
+    /// Sends a request to a node of `upstream.pool`, retrying on other nodes and
+    /// proxies when it fails or is rate limited.
+    pub async fn forward(
+        network: Network,
+        provider: Arc<Provider>,
+        proxy_provider: Arc<ProxyProvider>,
+        req: Request<Body>,
+        upstream: Upstream,
     ) -> Response {
         let start_time = Instant::now();
         let mut retries = 0;
@@ -142,12 +306,45 @@ impl Proxy {
         let method = parts.method;
         let headers = parts.headers;
         let body_bytes = body.collect().await.unwrap().to_bytes();
+        let rpc_methods = rpc_methods(&body_bytes);
+        let transactions = parts
+            .extensions
+            .get::<Vec<TransactionSummary>>()
+            .cloned()
+            .unwrap_or_default();
+        let access = AccessContext::from_extensions(&parts.extensions);
+        let request_id = parts
+            .extensions
+            .get::<RequestId>()
+            .map(RequestId::to_string)
+            .unwrap_or_default();
+        let _in_flight = InFlight::start(network.as_ref());
+        let completed = |node: Option<&str>, status: StatusCode, attempts: usize, proxy: &str| {
+            metrics::record_request(
+                network.as_ref(),
+                node,
+                &rpc_methods,
+                status,
+                proxy,
+                start_time.elapsed(),
+            );
+            events::publish(Event::RequestCompleted {
+                network: network.to_string(),
+                methods: rpc_methods.clone(),
+                node: node.map(redact_url),
+                status: status.as_u16(),
+                latency_ms: start_time.elapsed().as_millis() as u64,
+                attempts,
+                transactions: transactions.clone(),
+            });
+        };
 
         loop {
-            let rpc_url = match provider.get_node_url(network).await {
+            let rpc_url = match provider.get_pool_node_url(network, upstream.pool).await {
                 Some(url) => url,
                 None => {
                     error!("Error getting node URL. Network: {:?}", network.to_string());
+                    completed(None, StatusCode::INTERNAL_SERVER_ERROR, retries + 1, "none");
                     return Self::error_response(
                         StatusCode::INTERNAL_SERVER_ERROR,
                         format!("Error getting node URL. Network: {:?}", network.to_string()),
@@ -155,26 +352,100 @@ impl Proxy {
                 }
             };
             debug!("RPC URL: {}", rpc_url);
+            let request_url = match &upstream.path {
+                Some(path) => match join_path(&rpc_url, path) {
+                    Some(url) => url,
+                    None => {
+                        completed(Some(&rpc_url), StatusCode::BAD_REQUEST, retries + 1, "none");
+                        return Self::error_response(
+                            StatusCode::BAD_REQUEST,
+                            "Invalid path".to_string(),
+                        );
+                    }
+                },
+                None => rpc_url.clone(),
+            };
 
             // Get a new proxy URL if needed
             if proxy.current_proxy_url.is_none() {
                 proxy.current_proxy_url = proxy_provider.get_proxy_url(ProxyType::Socks5);
                 debug!("Using proxy URL: {:?}", proxy.current_proxy_url);
             }
+            if proxy.current_local_address.is_none() {
+                proxy.current_local_address = proxy_provider.get_local_address();
+                debug!("Using local address: {:?}", proxy.current_local_address);
+            }
 
+            let proxy_label = metrics::proxy_label(
+                proxy.current_proxy_url.as_deref(),
+                proxy.current_local_address,
+            );
+            let span = tracing::debug_span!(
+                "attempt",
+                request_id = %request_id,
+                network = %network,
+                attempt = retries + 1,
+                node = %redact_url(&rpc_url),
+                proxy = %proxy_label,
+            );
+            access.update(|details| {
+                details.nodes.push(redact_url(&rpc_url));
+                details.proxy = Some(proxy_label.clone());
+                details.attempts += 1;
+            });
+            let attempt_start = Instant::now();
+            let node_attempt = provider.start_attempt(network, &rpc_url);
             let response = proxy
-                .send_request(&rpc_url, &method, &headers, &body_bytes)
+                .send_request(
+                    &request_url,
+                    &method,
+                    &headers,
+                    &body_bytes,
+                    upstream.timeout,
+                )
+                .instrument(span.clone())
                 .await;
+            let duration_ms = attempt_start.elapsed().as_millis() as u64;
+            node_attempt.finish(response.as_ref().is_ok_and(|resp| {
+                resp.status() != StatusCode::TOO_MANY_REQUESTS && !resp.status().is_server_error()
+            }));
+            match &response {
+                Ok(resp) => {
+                    access.update(|details| details.upstream_status = Some(resp.status().as_u16()));
+                    debug!(
+                        parent: &span,
+                        status = resp.status().as_u16(),
+                        duration_ms,
+                        "Upstream attempt finished"
+                    );
+                }
+                Err(e) => warn!(parent: &span, error = %e, duration_ms, "Upstream attempt failed"),
+            }
 
             match response {
                 Ok(resp) => {
+                    if resp.status() == StatusCode::TOO_MANY_REQUESTS {
+                        metrics::record_upstream_error(
+                            network.as_ref(),
+                            &rpc_url,
+                            &proxy_label,
+                            ErrorKind::RateLimited,
+                            retries < MAX_RETRIES,
+                        );
+                    }
                     if resp.status() == StatusCode::TOO_MANY_REQUESTS && retries < MAX_RETRIES {
                         retries += 1;
                         warn!(
                             "Received 429 status. Retrying with a new node and proxy. Attempt: {}",
                             retries
                         );
-                        proxy.current_proxy_url = None; // Reset proxy URL to get a new one
+                        events::publish(Event::Retry {
+                            network: network.to_string(),
+                            node: redact_url(&rpc_url),
+                            attempt: retries,
+                            reason: resp.status().to_string(),
+                        });
+                        proxy.cool_down(); // Cool down and reset proxy URL and local address
                         continue;
                     }
                     let duration = start_time.elapsed();
@@ -184,19 +455,39 @@ impl Proxy {
                         duration,
                         resp.status()
                     );
+                    completed(Some(&rpc_url), resp.status(), retries + 1, &proxy_label);
                     return resp;
                 }
                 Err(e) => {
+                    metrics::record_upstream_error(
+                        network.as_ref(),
+                        &rpc_url,
+                        &proxy_label,
+                        ErrorKind::of(&e),
+                        retries < MAX_RETRIES,
+                    );
                     if retries < MAX_RETRIES {
                         retries += 1;
                         error!(
                             "Error sending request: {:?}. Retrying with a new node and proxy. Attempt: {}",
                             e, retries
                         );
-                        proxy.current_proxy_url = None; // Reset proxy URL to get a new one
+                        events::publish(Event::Retry {
+                            network: network.to_string(),
+                            node: redact_url(&rpc_url),
+                            attempt: retries,
+                            reason: e.to_string(),
+                        });
+                        proxy.rotate(); // Reset proxy URL and local address to get new ones
                         continue;
                     }
                     error!("Max retries reached. Error: {:?}", e);
+                    completed(
+                        Some(&rpc_url),
+                        StatusCode::BAD_GATEWAY,
+                        retries + 1,
+                        &proxy_label,
+                    );
                     return Self::error_response(StatusCode::BAD_GATEWAY, format!("Error: {}", e));
                 }
             }
@@ -209,9 +500,10 @@ impl Proxy {
         method: &Method,
         headers: &HeaderMap,
         body: &Bytes,
+        timeout: Duration,
     ) -> Result<Response, reqwest::Error> {
         let mut client_builder = Client::builder()
-            .timeout(Duration::from_secs(15))
+            .timeout(timeout)
             .connect_timeout(Duration::from_secs(10));
 
         // Add proxy if configured
@@ -219,6 +511,11 @@ impl Proxy {
             client_builder = client_builder.proxy(reqwest::Proxy::all(proxy_url)?);
         }
 
+        // Bind outbound connections to a local address if configured
+        if let Some(local_address) = self.current_local_address {
+            client_builder = client_builder.local_address(local_address);
+        }
+
         let http_client = client_builder.build()?;
 
         let mut request_headers = headers.clone();
@@ -243,9 +540,9 @@ impl Proxy {
         let status = reqwest_response.status();
         let headers = reqwest_response.headers().clone();
 
-        let stream = reqwest_response.bytes_stream().map(|result| {
-            result.map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))
-        });
+        let stream = reqwest_response
+            .bytes_stream()
+            .map(|result| result.map_err(std::io::Error::other));
 
         let body = Body::from_stream(stream);
 
@@ -258,10 +555,202 @@ impl Proxy {
         Ok(axum_response)
     }
 
-    fn error_response(status: StatusCode, message: String) -> Response {
+    /// Sends one JSON-RPC call to a given node through the proxy pool and returns
+    /// its `result`, or the error message.
+    pub(crate) async fn call(
+        network: Network,
+        proxy_provider: Arc<ProxyProvider>,
+        rpc_url: &str,
+        request: &Value,
+    ) -> Result<Value, String> {
+        let mut proxy = Proxy::new(proxy_provider.clone());
+        proxy.current_proxy_url = proxy_provider.get_proxy_url(ProxyType::Socks5);
+        proxy.current_local_address = proxy_provider.get_local_address();
+        let proxy_label = metrics::proxy_label(
+            proxy.current_proxy_url.as_deref(),
+            proxy.current_local_address,
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        let response = proxy
+            .send_request(
+                rpc_url,
+                &Method::POST,
+                &headers,
+                &Bytes::from(request.to_string()),
+                REQUEST_TIMEOUT,
+            )
+            .await
+            .map_err(|e| {
+                let kind = ErrorKind::of(&e);
+                metrics::record_upstream_error(
+                    network.as_ref(),
+                    rpc_url,
+                    &proxy_label,
+                    kind,
+                    false,
+                );
+                e.without_url().to_string()
+            })?;
+
+        let status = response.status();
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            metrics::record_upstream_error(
+                network.as_ref(),
+                rpc_url,
+                &proxy_label,
+                ErrorKind::RateLimited,
+                false,
+            );
+            proxy.cool_down();
+        }
+        let body = response
+            .into_body()
+            .collect()
+            .await
+            .map_err(|e| e.to_string())?
+            .to_bytes();
+        match serde_json::from_slice::<Value>(&body) {
+            Ok(Value::Object(mut response)) => match response.remove("error") {
+                Some(error) => Err(error["message"].as_str().unwrap_or("error").to_string()),
+                None => Ok(response.remove("result").unwrap_or_default()),
+            },
+            _ => Err(format!("Upstream error. Status: {}", status)),
+        }
+    }
+
+    pub(crate) fn error_response(status: StatusCode, message: String) -> Response {
         Response::builder()
             .status(status)
             .body(Body::from(message))
             .unwrap()
     }
 }
+
+/// Appends a sub-path and query string to a node's base URL, keeping the base's
+/// own path and query. `None` if the sub-path would leave the base path.
+fn join_path(base: &str, path: &str) -> Option<String> {
+    let mut url = Url::parse(base).ok()?;
+    let (path, query) = match path.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (path, None),
+    };
+
+    let base_path = url.path().trim_end_matches('/').to_string();
+    url.set_path(&format!("{}/{}", base_path, path.trim_start_matches('/')));
+    if !url.path().starts_with(&format!("{}/", base_path)) {
+        return None;
+    }
+    match (url.query().map(String::from), query) {
+        (Some(base_query), Some(query)) => {
+            url.set_query(Some(&format!("{}&{}", base_query, query)))
+        }
+        (None, Some(query)) => url.set_query(Some(query)),
+        _ => {}
+    }
+    Some(url.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn proxy_provider(json: &str) -> Result<ProxyProvider, ProxyProviderError> {
+        ProxyProvider::from_json(serde_json::from_str(json).unwrap())
+    }
+
+    #[test]
+    fn test_local_address_rotation_and_cooldown() {
+        let provider =
+            proxy_provider(r#"{"local_address": ["10.0.0.1", "10.0.0.2", "::1"]}"#).unwrap();
+
+        assert_eq!(
+            provider.get_local_address(),
+            Some("10.0.0.1".parse().unwrap())
+        );
+        assert_eq!(
+            provider.get_local_address(),
+            Some("10.0.0.2".parse().unwrap())
+        );
+        assert_eq!(provider.get_local_address(), Some("::1".parse().unwrap()));
+
+        provider.cool_down("10.0.0.2");
+        assert_eq!(
+            provider.get_local_address(),
+            Some("10.0.0.1".parse().unwrap())
+        );
+        assert_eq!(provider.get_local_address(), Some("::1".parse().unwrap()));
+        assert_eq!(
+            provider.get_local_address(),
+            Some("10.0.0.1".parse().unwrap())
+        );
+
+        assert_eq!(provider.get_proxy_url(ProxyType::Socks5), None);
+    }
+
+    #[test]
+    fn test_invalid_local_address() {
+        assert!(matches!(
+            proxy_provider(r#"{"local-address": ["not-an-ip"]}"#),
+            Err(ProxyProviderError::InvalidLocalAddress(_))
+        ));
+    }
+
+    #[test]
+    fn test_join_path() {
+        assert_eq!(
+            join_path(
+                "https://node.example/v2/key",
+                "eth/v1/node/health?verbose=1"
+            )
+            .as_deref(),
+            Some("https://node.example/v2/key/eth/v1/node/health?verbose=1")
+        );
+        assert_eq!(
+            join_path("https://node.example/?api-key=k", "health").as_deref(),
+            Some("https://node.example/health?api-key=k")
+        );
+        assert_eq!(
+            join_path("https://node.example/rpc?api-key=k", "v1/status?full=true").as_deref(),
+            Some("https://node.example/rpc/v1/status?api-key=k&full=true")
+        );
+        assert_eq!(join_path("https://node.example/v2/key", "../other"), None);
+        assert_eq!(
+            join_path("https://node.example/v2/key", "a/%2e%2e/%2E%2E/x"),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_forward_sub_path() {
+        let node = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/base/eth/v1/node/health"))
+            .and(query_param("verbose", "1"))
+            .respond_with(ResponseTemplate::new(206))
+            .expect(1)
+            .mount(&node)
+            .await;
+
+        let provider = Arc::new(Provider::from_json(
+            serde_json::json!({"ethereum": [format!("{}/base", node.uri())]}),
+        ));
+        let proxy_provider =
+            Arc::new(ProxyProvider::new(String::new(), false, Duration::from_secs(1)).unwrap());
+
+        let req = Request::builder()
+            .method(Method::GET)
+            .body(Body::empty())
+            .unwrap();
+        let upstream = Upstream {
+            path: Some("eth/v1/node/health?verbose=1".to_string()),
+            ..Default::default()
+        };
+        let response =
+            Proxy::forward(Network::ETHEREUM, provider, proxy_provider, req, upstream).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    }
+}
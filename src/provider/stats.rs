@@ -0,0 +1,126 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Upstream attempts kept per node for its latency percentiles and error rate.
+const WINDOW: usize = 100;
+
+/// Recent activity of one node, as shown by the admin API.
+#[derive(Debug, Default)]
+pub(crate) struct NodeStats {
+    /// Latest attempts, oldest first, with whether the node answered properly.
+    attempts: VecDeque<(Duration, bool)>,
+    /// Attempts since the node was first seen, for throughput.
+    pub(crate) total: u64,
+    pub(crate) in_flight: usize,
+    /// Latest block or slot reported during verification.
+    pub(crate) head: Option<u64>,
+    pub(crate) verification: Option<Verification>,
+}
+
+impl NodeStats {
+    pub(crate) fn record(&mut self, duration: Duration, ok: bool) {
+        if self.attempts.len() == WINDOW {
+            self.attempts.pop_front();
+        }
+        self.attempts.push_back((duration, ok));
+        self.total += 1;
+    }
+
+    pub(crate) fn attempts(&self) -> usize {
+        self.attempts.len()
+    }
+
+    pub(crate) fn error_rate(&self) -> Option<f64> {
+        if self.attempts.is_empty() {
+            return None;
+        }
+        let errors = self.attempts.iter().filter(|(_, ok)| !ok).count();
+        Some(errors as f64 / self.attempts.len() as f64)
+    }
+
+    pub(crate) fn latency(&self) -> Option<Latency> {
+        let mut latencies: Vec<u64> = self
+            .attempts
+            .iter()
+            .map(|(duration, _)| duration.as_millis() as u64)
+            .collect();
+        if latencies.is_empty() {
+            return None;
+        }
+        latencies.sort_unstable();
+        // Nearest rank
+        let percentile = |p: usize| latencies[(p * latencies.len()).div_ceil(100) - 1];
+        Some(Latency {
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+        })
+    }
+}
+
+/// Latency percentiles of a node's recent attempts, in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Latency {
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+}
+
+/// Latest chain identity check of a node.
+#[derive(Debug, Clone, Serialize)]
+pub struct Verification {
+    pub at: DateTime<Utc>,
+    pub outcome: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeStatus {
+    /// URL with credentials and key-like segments redacted.
+    pub node: String,
+    pub pool: &'static str,
+    pub healthy: bool,
+    /// `open` while the node is quarantined and skipped by the rotation.
+    pub circuit: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quarantine_reason: Option<String>,
+    pub verification: Option<Verification>,
+    pub head: Option<u64>,
+    /// Blocks or slots behind the most advanced node of the network.
+    pub lag: Option<u64>,
+    pub latency_ms: Option<Latency>,
+    pub error_rate: Option<f64>,
+    /// Attempts the latency and error rate are computed over.
+    pub attempts: usize,
+    /// Attempts since the node was first seen; its rate of change is the throughput.
+    pub total_attempts: u64,
+    pub in_flight: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_node_stats_window() {
+        let mut stats = NodeStats::default();
+        assert_eq!(stats.latency(), None);
+        assert_eq!(stats.error_rate(), None);
+
+        for ms in 1..=WINDOW as u64 + 10 {
+            stats.record(Duration::from_millis(ms), ms % 10 != 0);
+        }
+        assert_eq!(stats.attempts(), WINDOW);
+        assert_eq!(stats.total, WINDOW as u64 + 10);
+        assert_eq!(
+            stats.latency(),
+            Some(Latency {
+                p50: 60,
+                p90: 100,
+                p99: 109
+            })
+        );
+        assert_eq!(stats.error_rate(), Some(0.1));
+    }
+}
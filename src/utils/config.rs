@@ -1,12 +1,98 @@
+use crate::app::auth::ApiKeyConfig;
+use crate::app::capture::CaptureConfig;
+use crate::app::rate_limit::RateLimitConfig;
+use crate::provider::NetworkConfig;
+use crate::utils::logger::{LogFormat, LoggingConfig};
+use clap::Parser;
 use config::{Config as Configuration, ConfigError, Environment, File};
 use serde::Deserialize;
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Config {
     pub http_server_address: String,
+    /// Address the admin status API listens on; unset disables it.
+    #[serde(default)]
+    pub admin_server_address: Option<String>,
     pub node_list_path: String,
     pub proxy_is_enabled: bool,
     pub proxy_list_path: String,
+    #[serde(default = "default_proxy_cooldown_secs")]
+    pub proxy_cooldown_secs: u64,
+    /// Token subscribers of the `/ws` event stream authenticate with; unset disables it.
+    #[serde(default)]
+    pub events_token: Option<String>,
+    /// Networks served in addition to (or overriding) the built-in ones.
+    #[serde(default)]
+    pub networks: Vec<NetworkConfig>,
+    /// How often every node's chain identity is re-verified.
+    #[serde(default = "default_node_verify_interval_secs")]
+    pub node_verify_interval_secs: u64,
+    /// JSON Lines file submitted Solana transactions are recorded to; unset disables it.
+    #[serde(default)]
+    pub audit_log_path: Option<String>,
+    /// JSON Lines file, or `stdout`, every client request is recorded to; unset disables it.
+    #[serde(default)]
+    pub access_log_path: Option<String>,
+    /// Sampling of full request and response bodies for the admin API; unset disables it.
+    #[serde(default)]
+    pub capture: Option<CaptureConfig>,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    /// Whether the `X-Request-Id` of a request is sent on to the nodes.
+    #[serde(default)]
+    pub forward_request_id: bool,
+    /// JSON file of API keys required on the RPC routes.
+    #[serde(default)]
+    pub api_keys_path: Option<String>,
+    /// API keys in addition to those of `api_keys_path`; with neither, the RPC routes are open.
+    #[serde(default)]
+    pub api_keys: Vec<ApiKeyConfig>,
+    /// Token buckets per client on the RPC routes; unset disables rate limiting.
+    #[serde(default)]
+    pub rate_limits: Option<RateLimitConfig>,
+}
+
+/// Command line options, taking precedence over the config file.
+#[derive(Debug, Clone, Default, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Log level directives, e.g. `info,tutus_nodus::provider=debug`
+    #[arg(long, env = "LOG_LEVEL")]
+    pub log_level: Option<String>,
+    #[arg(long, env = "LOG_FORMAT", value_enum)]
+    pub log_format: Option<LogFormat>,
+    /// Directory log files are written to
+    #[arg(long, env = "LOG_DIRECTORY")]
+    pub log_directory: Option<String>,
+    /// Log to stdout only
+    #[arg(long, conflicts_with = "log_directory")]
+    pub no_log_file: bool,
+}
+
+impl Cli {
+    pub fn apply(&self, config: &mut Config) {
+        let logging = &mut config.logging;
+        if let Some(level) = &self.log_level {
+            logging.level = level.clone();
+        }
+        if let Some(format) = self.log_format {
+            logging.format = format;
+        }
+        if let Some(directory) = &self.log_directory {
+            logging.directory = Some(directory.clone());
+        }
+        if self.no_log_file {
+            logging.directory = None;
+        }
+    }
+}
+
+fn default_proxy_cooldown_secs() -> u64 {
+    30
+}
+
+fn default_node_verify_interval_secs() -> u64 {
+    300
 }
 
 impl Config {
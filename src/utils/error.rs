@@ -20,3 +20,21 @@ pub enum ProviderError {
     #[error("Error while initializing provider")]
     InitializeProviderError,
 }
+
+#[derive(Error, Debug)]
+pub enum LoggerError {
+    #[error("Invalid log level directive: {0}")]
+    InvalidLevel(String),
+    #[error("Error opening log file")]
+    OpenLogFileError(IOError),
+    #[error("Logger is already set up")]
+    AlreadySetUp,
+}
+
+#[derive(Error, Debug)]
+pub enum ApiKeyError {
+    #[error("Error reading API keys file")]
+    ReadKeysFileError(IOError),
+    #[error("Error while parsing API keys file")]
+    ParseKeysFileError(serde_json::Error),
+}
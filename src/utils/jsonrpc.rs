@@ -0,0 +1,43 @@
+use serde_json::{json, Value};
+
+pub fn rpc_error(id: Value, code: i64, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message },
+    })
+}
+
+/// Methods of a JSON-RPC request or batch body; empty if the body is not JSON-RPC.
+pub fn rpc_methods(body: &[u8]) -> Vec<String> {
+    let method = |request: &Value| {
+        request
+            .get("method")
+            .and_then(Value::as_str)
+            .map(String::from)
+    };
+
+    match serde_json::from_slice::<Value>(body) {
+        Ok(Value::Array(batch)) => batch.iter().filter_map(method).collect(),
+        Ok(request) => method(&request).into_iter().collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rpc_methods() {
+        assert_eq!(
+            rpc_methods(br#"{"jsonrpc":"2.0","id":1,"method":"getSlot"}"#),
+            vec!["getSlot"]
+        );
+        assert_eq!(
+            rpc_methods(br#"[{"method":"eth_chainId"},{"method":"eth_blockNumber"}]"#),
+            vec!["eth_chainId", "eth_blockNumber"]
+        );
+        assert!(rpc_methods(b"not json").is_empty());
+    }
+}
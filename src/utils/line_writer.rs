@@ -0,0 +1,70 @@
+use log::error;
+use std::io::{BufWriter, Write};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+
+/// Appends lines to an output from a thread of its own, so request handling
+/// never waits on the disk. Lines are buffered and flushed whenever no more are
+/// queued.
+#[derive(Debug, Clone)]
+pub struct LineWriter {
+    tx: Sender<String>,
+}
+
+impl LineWriter {
+    pub fn spawn(name: &'static str, output: impl Write + Send + 'static) -> Self {
+        let (tx, rx) = mpsc::channel::<String>();
+        thread::Builder::new()
+            .name(name.replace(' ', "-"))
+            .spawn(move || {
+                let mut output = BufWriter::new(output);
+                while let Ok(line) = rx.recv() {
+                    // One write per line keeps a rotating output from splitting it
+                    let mut result = output.write_all(line.as_bytes());
+                    while let Ok(line) = rx.try_recv() {
+                        result = result.and(output.write_all(line.as_bytes()));
+                    }
+                    if let Err(e) = result.and_then(|_| output.flush()) {
+                        error!("Failed to write {}: {}", name, e);
+                    }
+                }
+            })
+            .expect("Failed to start writer thread");
+        Self { tx }
+    }
+
+    pub fn write(&self, mut line: String) {
+        line.push('\n');
+        let _ = self.tx.send(line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_line_writer() {
+        let path =
+            std::env::temp_dir().join(format!("tutus_nodus_lines_{}.log", std::process::id()));
+        let writer = LineWriter::spawn("test log", std::fs::File::create(&path).unwrap());
+        for i in 0..100 {
+            writer.write(format!("line {}", i));
+        }
+
+        let mut contents = String::new();
+        for _ in 0..100 {
+            contents = std::fs::read_to_string(&path).unwrap();
+            if contents.lines().count() == 100 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 100);
+        assert_eq!(lines[0], "line 0");
+        assert_eq!(lines[99], "line 99");
+    }
+}
@@ -1,9 +1,187 @@
-use chrono::Local;
+use crate::utils::error::LoggerError;
+use chrono::{Local, NaiveDate, Utc};
 use fern::colors::{Color, ColoredLevelConfig};
 use fern::Dispatch;
-use log::LevelFilter;
+use log::{LevelFilter, Metadata, Record};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use serde_json::json;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::RwLock;
+use std::thread;
+use std::time::{Duration, Instant};
 
-pub fn setup_logger(level: LevelFilter) {
+const LOG_FILE_NAME: &str = "tutus_nodus.log";
+/// How long a file that failed to rotate is written to before rotating is retried.
+const ROTATION_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Level directives in effect, replaceable at runtime.
+static LEVELS: Lazy<RwLock<Levels>> = Lazy::new(|| RwLock::new(Levels::default()));
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Human readable lines, colored on stdout.
+    #[default]
+    Text,
+    /// One JSON object per line.
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogRotation {
+    #[default]
+    Daily,
+    /// Whenever the file reaches `max_file_size_mb`.
+    Size,
+    Never,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    /// Default level followed by per-target overrides, as in
+    /// `info,tutus_nodus::provider=debug,hyper=warn`.
+    pub level: String,
+    pub format: LogFormat,
+    /// Directory log files are written to; unset logs to stdout only.
+    pub directory: Option<String>,
+    pub rotation: LogRotation,
+    pub max_file_size_mb: u64,
+    /// Rotated files kept besides the current one; 0 keeps all of them.
+    pub max_files: usize,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            format: LogFormat::Text,
+            directory: Some("./logs".to_string()),
+            rotation: LogRotation::Daily,
+            max_file_size_mb: 100,
+            max_files: 14,
+        }
+    }
+}
+
+/// A default level and the levels of targets it is overridden for.
+#[derive(Debug, Clone, PartialEq)]
+struct Levels {
+    default: LevelFilter,
+    /// Sorted longest first so the most specific target wins.
+    targets: Vec<(String, LevelFilter)>,
+}
+
+impl Default for Levels {
+    fn default() -> Self {
+        Self {
+            default: LevelFilter::Info,
+            targets: Vec::new(),
+        }
+    }
+}
+
+impl FromStr for Levels {
+    type Err = LoggerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut levels = Levels::default();
+        for directive in s.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let invalid = || LoggerError::InvalidLevel(directive.to_string());
+            match directive.split_once('=') {
+                Some((target, level)) => {
+                    let level = level.trim().parse().map_err(|_| invalid())?;
+                    levels.targets.push((target.trim().to_string(), level));
+                }
+                None => levels.default = directive.parse().map_err(|_| invalid())?,
+            }
+        }
+        levels
+            .targets
+            .sort_by_key(|(target, _)| std::cmp::Reverse(target.len()));
+        Ok(levels)
+    }
+}
+
+impl Levels {
+    fn level_for(&self, target: &str) -> LevelFilter {
+        self.targets
+            .iter()
+            .find(|(prefix, _)| {
+                target == prefix
+                    || target
+                        .strip_prefix(prefix.as_str())
+                        .is_some_and(|rest| rest.starts_with("::"))
+            })
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default)
+    }
+
+    fn max(&self) -> LevelFilter {
+        self.targets
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, std::cmp::max)
+    }
+
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level_for(metadata.target())
+    }
+}
+
+/// Installs the global logger: stdout plus, with a directory configured, a
+/// rotating log file. The directory is created if missing.
+pub fn setup_logger(config: &LoggingConfig) -> Result<(), LoggerError> {
+    set_level(&config.level)?;
+
+    let mut dispatch = Dispatch::new()
+        .filter(|metadata| LEVELS.read().unwrap().enabled(metadata))
+        .level(LevelFilter::Trace)
+        .chain(
+            Dispatch::new()
+                .format(formatter(config.format, true))
+                .chain(io::stdout()),
+        );
+
+    if let Some(directory) = &config.directory {
+        let file = RotatingFile::open(
+            PathBuf::from(directory),
+            LOG_FILE_NAME,
+            config.rotation,
+            config.max_file_size_mb * 1024 * 1024,
+            config.max_files,
+        )
+        .map_err(LoggerError::OpenLogFileError)?;
+        let output: Box<dyn Write + Send> = Box::new(file);
+        dispatch = dispatch.chain(
+            Dispatch::new()
+                .format(formatter(config.format, false))
+                .chain(output),
+        );
+    }
+
+    dispatch.apply().map_err(|_| LoggerError::AlreadySetUp)?;
+    // `apply` raises the global maximum to the dispatch level
+    log::set_max_level(LEVELS.read().unwrap().max());
+    Ok(())
+}
+
+/// Replaces the level directives of the running logger.
+pub fn set_level(directives: &str) -> Result<(), LoggerError> {
+    let levels: Levels = directives.parse()?;
+    log::set_max_level(levels.max());
+    *LEVELS.write().unwrap() = levels;
+    Ok(())
+}
+
+type Formatter = Box<dyn Fn(fern::FormatCallback, &std::fmt::Arguments, &Record) + Sync + Send>;
+
+fn formatter(format: LogFormat, colored: bool) -> Formatter {
     let colors_line = ColoredLevelConfig::new()
         .error(Color::BrightRed)
         .warn(Color::BrightYellow)
@@ -11,29 +189,205 @@ pub fn setup_logger(level: LevelFilter) {
         .debug(Color::Magenta)
         .trace(Color::BrightCyan);
 
-    let log_file_name = format!("./logs/logs_{}.log", Local::now().format("%d_%m_%y"));
+    match format {
+        LogFormat::Text => Box::new(move |out, message, record| {
+            let timestamp = Local::now().format("[%Y-%m-%d][%H:%M:%S:%3f]");
+            if colored {
+                out.finish(format_args!(
+                    "{} {} [{}] {}",
+                    timestamp,
+                    colors_line.color(record.level()),
+                    record.target(),
+                    message
+                ))
+            } else {
+                out.finish(format_args!(
+                    "{} {} [{}] {}",
+                    timestamp,
+                    record.level(),
+                    record.target(),
+                    message
+                ))
+            }
+        }),
+        LogFormat::Json => Box::new(|out, message, record| {
+            out.finish(format_args!("{}", json_line(message, record)))
+        }),
+    }
+}
+
+fn json_line(message: &std::fmt::Arguments, record: &Record) -> serde_json::Value {
+    json!({
+        "timestamp": Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        "level": record.level().as_str(),
+        "target": record.target(),
+        "message": message.to_string(),
+    })
+}
+
+/// A file such as `tutus_nodus.log` in a directory, renamed with a timestamp
+/// before its extension once it is rotated, keeping at most `max_files`
+/// rotated files.
+pub(crate) struct RotatingFile {
+    directory: PathBuf,
+    /// File name without its extension, which rotated names start with.
+    stem: String,
+    /// Extension including its dot, or empty.
+    extension: String,
+    rotation: LogRotation,
+    max_bytes: u64,
+    max_files: usize,
+    file: File,
+    written: u64,
+    opened_on: NaiveDate,
+    /// Last failed rotation, if the file has not been rotated since.
+    rotation_failed_at: Option<Instant>,
+}
 
-    let log_file = fern::log_file(&log_file_name).unwrap();
+impl RotatingFile {
+    pub(crate) fn open(
+        directory: PathBuf,
+        file_name: &str,
+        rotation: LogRotation,
+        max_bytes: u64,
+        max_files: usize,
+    ) -> io::Result<Self> {
+        fs::create_dir_all(&directory)?;
+        let (stem, extension) = match file_name.rsplit_once('.') {
+            Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{}", extension)),
+            _ => (file_name, String::new()),
+        };
+        let path = directory.join(file_name);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let metadata = file.metadata()?;
+        let opened_on = metadata
+            .modified()
+            .map(|modified| chrono::DateTime::<Local>::from(modified).date_naive())
+            .unwrap_or_else(|_| Local::now().date_naive());
 
-    Dispatch::new()
-        .format(move |out, message, record| {
-            out.finish(format_args!(
-                "{} {} [{}] {}",
-                Local::now().format("[%Y-%m-%d][%H:%M:%S:%3f]"),
-                colors_line.color(record.level()),
-                record.target(),
-                message
-            ))
+        Ok(Self {
+            directory,
+            stem: stem.to_string(),
+            extension,
+            rotation,
+            max_bytes: max_bytes.max(1),
+            max_files,
+            file,
+            written: metadata.len(),
+            opened_on,
+            rotation_failed_at: None,
         })
-        .level(level)
-        .chain(std::io::stdout())
-        .chain(log_file)
-        .apply()
-        .unwrap();
+    }
+
+    fn file_name(&self) -> String {
+        format!("{}{}", self.stem, self.extension)
+    }
+
+    fn needs_rotation(&self, incoming: usize) -> bool {
+        let retry_due = self
+            .rotation_failed_at
+            .is_none_or(|failed_at| failed_at.elapsed() >= ROTATION_RETRY_INTERVAL);
+        if !retry_due {
+            return false;
+        }
+        match self.rotation {
+            LogRotation::Daily => Local::now().date_naive() != self.opened_on,
+            LogRotation::Size => {
+                self.written > 0 && self.written + incoming as u64 > self.max_bytes
+            }
+            LogRotation::Never => false,
+        }
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let current = self.directory.join(self.file_name());
+        let stamp = Local::now().format("%Y%m%d-%H%M%S");
+        // The counter tells apart files rotated within the same second
+        let rotated = (0..)
+            .map(|n| {
+                self.directory.join(format!(
+                    "{}.{}-{:03}{}",
+                    self.stem, stamp, n, self.extension
+                ))
+            })
+            .find(|path| !path.exists())
+            .unwrap();
+        fs::rename(&current, rotated)?;
+
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&current)?;
+        self.written = 0;
+        self.opened_on = Local::now().date_naive();
+        self.remove_old_files()
+    }
+
+    fn remove_old_files(&self) -> io::Result<()> {
+        if self.max_files == 0 {
+            return Ok(());
+        }
+        let prefix = format!("{}.", self.stem);
+        let current = self.file_name();
+        let mut rotated: Vec<PathBuf> = fs::read_dir(&self.directory)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| {
+                        name.starts_with(&prefix)
+                            && name.ends_with(&self.extension)
+                            && name != current
+                    })
+            })
+            .collect();
+        // Timestamps sort chronologically
+        rotated.sort();
+        let excess = rotated.len().saturating_sub(self.max_files);
+        for path in &rotated[..excess] {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.needs_rotation(buf.len()) {
+            match self.rotate() {
+                Ok(()) => self.rotation_failed_at = None,
+                Err(e) => {
+                    // Reported once until a rotation succeeds, from another thread as
+                    // the logger may be the one writing
+                    if self.rotation_failed_at.is_none() {
+                        let file = self.directory.join(self.file_name());
+                        thread::spawn(move || {
+                            log::error!(
+                                "Failed to rotate {}, writing on to it: {}",
+                                file.display(),
+                                e
+                            )
+                        });
+                    }
+                    self.rotation_failed_at = Some(Instant::now());
+                }
+            }
+        }
+        let written = self.file.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::{LogRotation, RotatingFile};
     use chrono::Local;
     use fern::colors::{Color, ColoredLevelConfig};
     use fern::Dispatch;
@@ -84,4 +438,101 @@ mod tests {
         assert!(log_content.contains("INFO"));
         assert!(log_content.contains("This is a test log message"));
     }
+
+    #[test]
+    fn test_level_directives() {
+        let levels: super::Levels =
+            "warn, tutus_nodus::provider=debug, tutus_nodus::provider::proxy=trace"
+                .parse()
+                .unwrap();
+        assert_eq!(levels.level_for("hyper::client"), LevelFilter::Warn);
+        assert_eq!(
+            levels.level_for("tutus_nodus::provider"),
+            LevelFilter::Debug
+        );
+        assert_eq!(
+            levels.level_for("tutus_nodus::provider::network"),
+            LevelFilter::Debug
+        );
+        assert_eq!(
+            levels.level_for("tutus_nodus::provider::proxy"),
+            LevelFilter::Trace
+        );
+        assert_eq!(
+            levels.level_for("tutus_nodus::providers"),
+            LevelFilter::Warn
+        );
+        assert_eq!(levels.max(), LevelFilter::Trace);
+
+        assert!("info,hyper=loud".parse::<super::Levels>().is_err());
+    }
+
+    #[test]
+    fn test_size_rotation_and_retention() {
+        let directory =
+            std::env::temp_dir().join(format!("tutus_nodus_logs_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+
+        let mut file = RotatingFile::open(
+            directory.clone(),
+            "tutus_nodus.log",
+            LogRotation::Size,
+            10,
+            2,
+        )
+        .unwrap();
+        for line in [
+            "first line\n",
+            "second line\n",
+            "third line\n",
+            "fourth line\n",
+        ] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        file.flush().unwrap();
+
+        let mut names: Vec<String> = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        assert_eq!(names.len(), 3);
+        assert!(names.contains(&"tutus_nodus.log".to_string()));
+        let current = std::fs::read_to_string(directory.join("tutus_nodus.log")).unwrap();
+        assert_eq!(current, "fourth line\n");
+        // The oldest rotated file was removed
+        let rotated: Vec<String> = names
+            .iter()
+            .filter(|name| *name != "tutus_nodus.log")
+            .map(|name| std::fs::read_to_string(directory.join(name)).unwrap())
+            .collect();
+        assert_eq!(rotated, vec!["second line\n", "third line\n"]);
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_failed_rotation_keeps_writing() {
+        let directory =
+            std::env::temp_dir().join(format!("tutus_nodus_logs_gone_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+
+        let mut file = RotatingFile::open(
+            directory.clone(),
+            "tutus_nodus.log",
+            LogRotation::Size,
+            10,
+            2,
+        )
+        .unwrap();
+        file.write_all(b"first line\n").unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        // Rotating fails without the directory; the open file is written on
+        file.write_all(b"second line\n").unwrap();
+        let failed_at = file.rotation_failed_at.unwrap();
+        file.write_all(b"third line\n").unwrap();
+        assert_eq!(file.rotation_failed_at, Some(failed_at));
+        assert_eq!(file.written, 34);
+    }
 }
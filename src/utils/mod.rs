@@ -1,3 +1,6 @@
 pub mod config;
 pub mod error;
+pub mod jsonrpc;
+pub mod line_writer;
 pub mod logger;
+pub mod redact;